@@ -0,0 +1,183 @@
+//! Compares filtering the borrowed `TransactionInstructions` by program id against the previous `extract_instructions`,
+//! which cloned the transaction and decompiled every instruction, on swaps that do and do not go through pump.fun.
+//!
+//! Run with `cargo bench --bench instructions`.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::{AccountMeta, CompiledInstruction, Instruction};
+use solana_sdk::message::v0::{self, LoadedAddresses, LoadedMessage};
+use solana_sdk::message::{AccountKeys, AddressLookupTableAccount, VersionedMessage};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+use solana_transaction_status::{InnerInstruction, InnerInstructions, TransactionStatusMeta};
+use std::collections::HashSet;
+use std::hint::black_box;
+use std::str::FromStr;
+use week_edu_starter::config::InstructionDecoding;
+use week_edu_starter::core::TransactionInstructions;
+use week_edu_starter::pumpfun::instructions::BUY_IX_DISC;
+use week_edu_starter::tx_senders::constants::PUMP_FUN_PROGRAM_ADDR;
+
+/// A v0 swap through a router. The message sets the compute budget and calls the router, which invokes `program` with
+/// 16 accounts from a lookup table, which invokes itself with an event the way anchor emits them.
+fn router_swap(program: Pubkey) -> (TransactionStatusMeta, VersionedTransaction) {
+    let payer = Pubkey::new_unique();
+    let router = Pubkey::new_unique();
+    let table = AddressLookupTableAccount {
+        key: Pubkey::new_unique(),
+        addresses: (0..16).map(|_| Pubkey::new_unique()).collect(),
+    };
+    let swap_accounts: Vec<AccountMeta> = table
+        .addresses
+        .iter()
+        .enumerate()
+        .map(|(i, address)| AccountMeta {
+            pubkey: *address,
+            is_signer: false,
+            is_writable: i % 2 == 0,
+        })
+        .collect();
+    let mut router_accounts = vec![AccountMeta::new(payer, true), AccountMeta::new_readonly(program, false)];
+    router_accounts.extend(swap_accounts.iter().cloned());
+
+    let instructions = [
+        ComputeBudgetInstruction::set_compute_unit_limit(200_000),
+        ComputeBudgetInstruction::set_compute_unit_price(100_000),
+        Instruction::new_with_bytes(router, &[1; 64], router_accounts),
+    ];
+    let message = v0::Message::try_compile(&payer, &instructions, &[table.clone()], Hash::new_unique()).unwrap();
+    let lookup = &message.address_table_lookups[0];
+    let loaded_addresses = LoadedAddresses {
+        writable: lookup.writable_indexes.iter().map(|i| table.addresses[*i as usize]).collect(),
+        readonly: lookup.readonly_indexes.iter().map(|i| table.addresses[*i as usize]).collect(),
+    };
+
+    let mut swap_data = vec![2; 24];
+    swap_data[..8].copy_from_slice(&BUY_IX_DISC);
+    let account_keys = AccountKeys::new(&message.account_keys, Some(&loaded_addresses));
+    let inner_instructions = InnerInstructions {
+        index: 2,
+        instructions: [
+            (
+                Instruction::new_with_bytes(program, &swap_data, swap_accounts.clone()),
+                2,
+            ),
+            (
+                Instruction::new_with_bytes(program, &[3; 128], swap_accounts[..1].to_vec()),
+                3,
+            ),
+        ]
+        .into_iter()
+        .map(|(instruction, stack_height)| InnerInstruction {
+            instruction: account_keys.compile_instructions(&[instruction]).remove(0),
+            stack_height: Some(stack_height),
+        })
+        .collect(),
+    };
+
+    let meta = TransactionStatusMeta {
+        inner_instructions: Some(vec![inner_instructions]),
+        loaded_addresses,
+        ..TransactionStatusMeta::default()
+    };
+    let transaction = VersionedTransaction {
+        signatures: vec![Signature::default()],
+        message: VersionedMessage::V0(message),
+    };
+    (meta, transaction)
+}
+
+/// `extract_instructions` before `TransactionInstructions`, for v0 messages: clones the transaction, builds a
+/// `LoadedMessage` and decompiles every instruction. Returns (instruction, stack height, parent index).
+fn cloning_extract_instructions(
+    meta_data: TransactionStatusMeta,
+    transaction: VersionedTransaction,
+) -> Vec<(Instruction, u32, Option<usize>)> {
+    let message = transaction.message.clone();
+    let meta = meta_data.clone();
+    let inner_instructions = meta.inner_instructions.unwrap_or_default();
+    message.sanitize().unwrap();
+    let VersionedMessage::V0(v0) = message else {
+        unreachable!()
+    };
+    let loaded_addresses = LoadedAddresses {
+        writable: meta.loaded_addresses.writable.to_vec(),
+        readonly: meta.loaded_addresses.readonly.to_vec(),
+    };
+    let loaded_message = LoadedMessage::new(v0.clone(), loaded_addresses, &HashSet::new());
+
+    let decompile = |compiled: &CompiledInstruction| {
+        let account_keys = loaded_message.account_keys();
+        Instruction {
+            program_id: *account_keys.get(compiled.program_id_index as usize).unwrap(),
+            accounts: compiled
+                .accounts
+                .iter()
+                .map(|index| AccountMeta {
+                    pubkey: *account_keys.get(*index as usize).unwrap(),
+                    is_writable: loaded_message.is_writable(*index as usize),
+                    is_signer: loaded_message.is_signer(*index as usize),
+                })
+                .collect(),
+            data: compiled.data.clone(),
+        }
+    };
+
+    let mut instructions = Vec::new();
+    for (index, compiled) in v0.instructions.iter().enumerate() {
+        let top_level_index = instructions.len();
+        instructions.push((decompile(compiled), 1, None));
+        let Some(inner) = inner_instructions.iter().find(|inner| inner.index as usize == index) else {
+            continue;
+        };
+        let mut stack = vec![top_level_index];
+        for inner_instruction in &inner.instructions {
+            let stack_height = inner_instruction.stack_height.unwrap_or(2).max(2);
+            stack.truncate(stack_height as usize - 1);
+            let parent_index = stack.last().copied();
+            stack.push(instructions.len());
+            instructions.push((decompile(&inner_instruction.instruction), stack_height, parent_index));
+        }
+    }
+    instructions
+}
+
+fn instructions(c: &mut Criterion) {
+    let pump_fun_program = Pubkey::from_str(PUMP_FUN_PROGRAM_ADDR).unwrap();
+
+    for (name, program) in [("pump.fun", pump_fun_program), ("other", Pubkey::new_unique())] {
+        let (meta, transaction) = router_swap(program);
+
+        // what the handler did with every transaction
+        c.bench_function(&format!("cloning_extract_instructions/{}", name), |b| {
+            b.iter(|| {
+                let instructions = cloning_extract_instructions(meta.clone(), transaction.clone());
+                black_box(
+                    instructions
+                        .into_iter()
+                        .filter(|(instruction, _, _)| instruction.program_id == pump_fun_program)
+                        .collect::<Vec<_>>(),
+                )
+            })
+        });
+
+        c.bench_function(&format!("for_program/{}", name), |b| {
+            b.iter(|| {
+                let instructions =
+                    TransactionInstructions::new(&meta, &transaction, InstructionDecoding::Strict).unwrap();
+                black_box(
+                    instructions
+                        .for_program(pump_fun_program)
+                        .map(|instruction| instruction.to_instruction().unwrap())
+                        .collect::<Vec<_>>(),
+                )
+            })
+        });
+    }
+}
+
+criterion_group!(benches, instructions);
+criterion_main!(benches);
@@ -1,68 +1,249 @@
-use crate::config::PingThingsArgs;
+use crate::blockhash::BlockhashService;
+use crate::clock::SlotClock;
+use crate::config::{PingThingsArgs, RpcType};
+use crate::confirmation::bundles::BundleStatusPoller;
+use crate::confirmation::{LandingStatus, SignatureTracker};
+use crate::metrics::{BenchReport, MetricsCollector};
+use crate::tx_senders::fees::PriorityFeeEstimator;
+use crate::tx_senders::jito::bundles_url;
 use crate::tx_senders::solana_rpc::TxMetrics;
-use crate::tx_senders::transaction::TransactionConfig;
-use crate::tx_senders::{TxSender, create_tx_sender};
+use crate::tx_senders::tip::TipAccounts;
+use crate::tx_senders::transaction::{TransactionConfig, warm_up_compute_units};
+use crate::tx_senders::{TxResult, TxSender, create_tx_sender};
 use reqwest::Client;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::hash::Hash;
 use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
-use tracing::{error, info};
+use std::time::Duration;
+use tracing::{debug, error, info};
+
+// the block engine rarely rotates its tip accounts
+const TIP_ACCOUNTS_REFRESH_INTERVAL: Duration = Duration::from_secs(600);
+const TIP_ACCOUNTS_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
 
 #[derive(Clone)]
 pub struct Bench {
-    config: PingThingsArgs,
-    tx_subscribe_sender: tokio::sync::mpsc::Sender<TxMetrics>,
+    metrics: MetricsCollector,
+    http_rpc: Arc<RpcClient>,
+    slot_clock: Arc<SlotClock>,
+    blockhash: Arc<BlockhashService>,
+    signature_tracker: SignatureTracker,
+    priority_fee: Arc<PriorityFeeEstimator>,
+    // rpc_name -> poller for jito senders
+    bundle_pollers: HashMap<String, BundleStatusPoller>,
     rpcs: Vec<Arc<dyn TxSender>>,
-    client: Client,
+    // log the buy instead of sending it
+    dry_run: bool,
 }
 
 impl Bench {
-    pub fn new(config: PingThingsArgs) -> Self {
-        let (tx_subscribe_sender, _tx_subscribe_receiver) = tokio::sync::mpsc::channel(100);
-        let tx_config: TransactionConfig = config.clone().into();
+    pub fn new(config: PingThingsArgs, slot_clock: Arc<SlotClock>) -> anyhow::Result<Self> {
+        let metrics = MetricsCollector::spawn(100);
+        let http_rpc = Arc::new(RpcClient::new_with_commitment(
+            config.http_rpc.clone(),
+            CommitmentConfig::processed(),
+        ));
+        let blockhash = Arc::new(BlockhashService::new(config.blockhash.clone(), http_rpc.clone()));
+        let signature_tracker = SignatureTracker::spawn(http_rpc.clone(), config.confirmation.clone());
+        let tx_config = TransactionConfig::try_from(config.clone())?;
         let client = Client::new();
 
+        if config.tip_accounts.is_none() {
+            Self::spawn_tip_accounts_refresh(&config, &tx_config, &client);
+        }
+        tx_config.tip.clone().spawn_refresh(client.clone());
+        tx_config.compute_unit_price.clone().spawn_refresh(http_rpc.clone());
+        if let (true, Some(mint)) = (config.compute_units.auto, &config.compute_units.warmup_mint) {
+            match Pubkey::from_str(mint) {
+                Ok(mint) => warm_up_compute_units(&tx_config, mint),
+                Err(e) => error!("invalid compute unit warmup mint {}: {:?}", mint, e),
+            }
+        }
+        let priority_fee = tx_config.compute_unit_price.clone();
+
+        let bundle_pollers = config
+            .rpc
+            .iter()
+            .filter(|(_, rpc)| matches!(rpc.rpc_type, RpcType::Jito))
+            .map(|(name, rpc)| {
+                let poller = BundleStatusPoller::spawn(
+                    bundles_url(&rpc.url_with_auth()),
+                    rpc.auth_header(),
+                    client.clone(),
+                    config.confirmation.clone(),
+                );
+                (name.clone(), poller)
+            })
+            .collect::<HashMap<String, BundleStatusPoller>>();
+
         let rpcs = config
             .rpc
             .clone()
             .into_iter()
             .map(|(name, rpc)| create_tx_sender(name, rpc, tx_config.clone(), client.clone()))
-            .collect::<Vec<Arc<dyn TxSender>>>();
+            .collect::<anyhow::Result<Vec<Arc<dyn TxSender>>>>()?;
 
-        Bench {
-            config,
-            tx_subscribe_sender,
+        Ok(Bench {
+            metrics,
+            http_rpc,
+            slot_clock,
+            blockhash,
+            signature_tracker,
+            priority_fee,
+            bundle_pollers,
             rpcs,
-            client,
-        }
+            dry_run: config.dry_run(),
+        })
+    }
+
+    fn spawn_tip_accounts_refresh(config: &PingThingsArgs, tx_config: &TransactionConfig, client: &Client) {
+        let Some(block_engine) = config.rpc.values().find(|rpc| matches!(rpc.rpc_type, RpcType::Jito)) else {
+            return;
+        };
+        let url = bundles_url(&block_engine.url_with_auth());
+        let auth_header = block_engine.auth_header();
+        let tip_accounts = tx_config.tip_accounts.clone();
+        let client = client.clone();
+
+        tokio::spawn(async move {
+            let mut retry_backoff = TIP_ACCOUNTS_INITIAL_BACKOFF;
+            loop {
+                match TipAccounts::fetch(&client, &url, auth_header.clone()).await {
+                    Ok(accounts) => {
+                        info!("using {} tip accounts from the block engine", accounts.len());
+                        tip_accounts.replace(accounts);
+                        retry_backoff = TIP_ACCOUNTS_INITIAL_BACKOFF;
+                        tokio::time::sleep(TIP_ACCOUNTS_REFRESH_INTERVAL).await;
+                    }
+                    Err(e) => {
+                        error!(
+                            "cannot fetch tip accounts, keeping the current ones and retrying in {:?}: {:?}",
+                            retry_backoff, e
+                        );
+                        tokio::time::sleep(retry_backoff).await;
+                        retry_backoff = (retry_backoff * 2).min(TIP_ACCOUNTS_REFRESH_INTERVAL);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Has to be fed geyser slot and block meta updates to follow the newest blockhash.
+    pub fn blockhash_service(&self) -> Arc<BlockhashService> {
+        self.blockhash.clone()
+    }
+
+    /// Called for every pump.fun create seen on the stream, buyers of fresh curves set the priority fee to beat.
+    pub fn observe_bonding_curve(&self, bonding_curve: Pubkey) {
+        self.priority_fee.observe_bonding_curve(bonding_curve);
+    }
+
+    pub async fn report(&self) -> anyhow::Result<BenchReport> {
+        self.metrics.report().await
     }
 
     pub async fn send_and_confirm_transaction(
+        &self,
         tx_index: u32,
         rpc_sender: Arc<dyn TxSender>,
         recent_blockhash: Hash,
         token_address: Pubkey,
         bonding_curve: Pubkey,
         associated_bonding_curve: Pubkey,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<TxMetrics> {
         let start = tokio::time::Instant::now();
 
-        let _tx_result = rpc_sender
-            .send_transaction(
+        // stamped from the geyser slot clock, the rpc is only asked until the clock has seen a slot and then
+        // alongside the send so it never delays the transaction itself
+        let clock_slot = self.slot_clock.current();
+        let (tx_result, slot_sent) = tokio::join!(
+            rpc_sender.send_transaction(
                 tx_index,
                 recent_blockhash,
                 token_address,
                 bonding_curve,
                 associated_bonding_curve,
-            )
-            .await?;
-
+            ),
+            async {
+                match clock_slot {
+                    Some(slot) => Ok(slot),
+                    None => self.http_rpc.get_slot().await,
+                }
+            }
+        );
         info!(
             "complete rpc: {:?} {:?} ms",
             rpc_sender.name(),
             start.elapsed().as_millis() as u64
         );
-        Ok(())
+
+        let slot_sent = slot_sent.unwrap_or_else(|e| {
+            error!("cannot fetch slot for {}: {:?}", rpc_sender.name(), e);
+            0
+        });
+        if let Some((leader_slot, leader)) = self.slot_clock.leader() {
+            debug!(
+                "{} sent at slot {}, last block {} by {}",
+                rpc_sender.name(),
+                slot_sent,
+                leader_slot,
+                leader
+            );
+        }
+
+        let mut tx_metrics = TxMetrics {
+            rpc_name: rpc_sender.name(),
+            signature: String::new(),
+            index: tx_index,
+            success: false,
+            slot_sent,
+            slot_landed: None,
+            slot_latency: None,
+            elapsed: None,
+            confirmation_status: None,
+            err: None,
+        };
+
+        match tx_result {
+            Ok(tx_result) => {
+                tx_metrics.signature = tx_result.clone().into();
+                let confirmation = match tx_result {
+                    TxResult::Signature(signature) => self.signature_tracker.confirm(signature).await,
+                    TxResult::BundleID(bundle_id) => match self.bundle_pollers.get(&rpc_sender.name()) {
+                        Some(poller) => poller.confirm(bundle_id).await,
+                        None => Err(anyhow::anyhow!("no bundle status poller for {}", rpc_sender.name())),
+                    },
+                };
+                // the transaction was sent either way, keep it in the report
+                match confirmation {
+                    Ok(confirmation) => {
+                        info!("{} {} {:?}", rpc_sender.name(), tx_metrics.signature, confirmation);
+
+                        tx_metrics.success = confirmation.is_success();
+                        if matches!(confirmation.status, LandingStatus::Landed | LandingStatus::Failed) {
+                            tx_metrics.slot_landed = confirmation.slot;
+                        }
+                        tx_metrics.confirmation_status = confirmation.confirmation_status;
+                        tx_metrics.err = confirmation.err;
+                    }
+                    Err(e) => {
+                        error!("cannot confirm {} {}: {:?}", rpc_sender.name(), tx_metrics.signature, e);
+                        tx_metrics.err = Some(e.to_string());
+                    }
+                }
+            }
+            Err(e) => {
+                error!("error send_transaction {}: {:?}", rpc_sender.name(), e);
+                tx_metrics.err = Some(e.to_string());
+            }
+        }
+        tx_metrics.elapsed = Some(start.elapsed().as_millis() as u64);
+
+        Ok(tx_metrics)
     }
 
     pub async fn send_buy_tx(
@@ -91,25 +272,38 @@ impl Bench {
     ) {
         let start = tokio::time::Instant::now();
         info!("starting create buy tx");
+        let recent_blockhash = self.blockhash.for_buy(recent_blockhash).await;
+        if self.dry_run {
+            info!(
+                "dry run, not sending buy of {} (bonding curve {}, blockhash {}) to {} rpcs",
+                token_address,
+                bonding_curve,
+                recent_blockhash,
+                self.rpcs.len()
+            );
+            return;
+        }
         let mut tx_handles = Vec::new();
 
         for rpc in &self.rpcs {
             // let rpc_name = rpc.name();
             let rpc_sender = rpc.clone();
-            // let client = self.client.clone();
+            let bench = self.clone();
             let hdl = tokio::spawn(async move {
                 let index = 0;
-                if let Err(e) = Self::send_and_confirm_transaction(
-                    index,
-                    rpc_sender,
-                    recent_blockhash,
-                    token_address,
-                    bonding_curve,
-                    associated_bonding_curve,
-                )
-                .await
+                match bench
+                    .send_and_confirm_transaction(
+                        index,
+                        rpc_sender,
+                        recent_blockhash,
+                        token_address,
+                        bonding_curve,
+                        associated_bonding_curve,
+                    )
+                    .await
                 {
-                    error!("error end_and_confirm_transaction {:?}", e);
+                    Ok(tx_metrics) => bench.metrics.record(tx_metrics).await,
+                    Err(e) => error!("error end_and_confirm_transaction {:?}", e),
                 }
             });
             tx_handles.push(hdl);
@@ -122,5 +316,14 @@ impl Bench {
         }
 
         info!("bench complete! {:?} ms", start.elapsed().as_millis() as u64);
+
+        match self.report().await {
+            Ok(report) => {
+                for (rpc_name, summary) in &report.summary {
+                    info!("bench result {}: {:?}", rpc_name, summary);
+                }
+            }
+            Err(e) => error!("cannot collect bench report: {:?}", e),
+        }
     }
 }
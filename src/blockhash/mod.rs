@@ -0,0 +1,131 @@
+use crate::config::{BlockhashConfig, BlockhashSource};
+use crate::geyser::handler::{BlockMetaUpdate, GeyserHandler, SlotUpdate};
+use async_trait::async_trait;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::{CommitmentConfig, CommitmentLevel};
+use solana_sdk::hash::Hash;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+use yellowstone_grpc_proto::geyser::SlotStatus;
+
+// blocks older than this many slots behind the newest blockhash are forgotten
+const MAX_PENDING_SLOTS: u64 = 300;
+
+#[derive(Debug, Clone, Copy)]
+struct RecentBlockhash {
+    slot: u64,
+    blockhash: Hash,
+    updated_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct BlockhashState {
+    latest: Option<RecentBlockhash>,
+    // slot -> blockhash of blocks that did not reach the commitment yet
+    blocks: BTreeMap<u64, Hash>,
+    // slots that reached the commitment before their block meta arrived
+    committed: BTreeSet<u64>,
+}
+
+/// Newest blockhash at the configured commitment, fed by blocks_meta and slot updates so blockhashes from forks that
+/// never got confirmed are not used. Falls back to `getLatestBlockhash` while the stream is quiet.
+pub struct BlockhashService {
+    config: BlockhashConfig,
+    http_rpc: Arc<RpcClient>,
+    state: Mutex<BlockhashState>,
+}
+
+impl BlockhashService {
+    pub fn new(config: BlockhashConfig, http_rpc: Arc<RpcClient>) -> Self {
+        BlockhashService {
+            config,
+            http_rpc,
+            state: Mutex::new(BlockhashState::default()),
+        }
+    }
+
+    /// Blockhash to buy with, the create transaction's own or the newest one depending on `source`.
+    pub async fn for_buy(&self, creator_blockhash: Hash) -> Hash {
+        match self.config.source {
+            BlockhashSource::Creator => creator_blockhash,
+            BlockhashSource::Latest => self.latest().await.unwrap_or_else(|e| {
+                error!("cannot get latest blockhash, using the creator's: {:?}", e);
+                creator_blockhash
+            }),
+        }
+    }
+
+    pub async fn latest(&self) -> anyhow::Result<Hash> {
+        let max_age = Duration::from_millis(self.config.max_age_ms);
+        if let Some(latest) = self.state.lock().unwrap().latest {
+            if latest.updated_at.elapsed() <= max_age {
+                return Ok(latest.blockhash);
+            }
+        }
+
+        let (blockhash, _) = self
+            .http_rpc
+            .get_latest_blockhash_with_commitment(CommitmentConfig {
+                commitment: self.config.commitment,
+            })
+            .await?;
+        debug!("geyser blockhash is stale, fetched {} from rpc", blockhash);
+        Ok(blockhash)
+    }
+
+    fn reached_commitment(&self, status: SlotStatus) -> bool {
+        match self.config.commitment {
+            CommitmentLevel::Processed => status == SlotStatus::SlotProcessed,
+            CommitmentLevel::Confirmed => status == SlotStatus::SlotConfirmed,
+            CommitmentLevel::Finalized => status == SlotStatus::SlotFinalized,
+        }
+    }
+
+    fn promote(state: &mut BlockhashState, slot: u64, blockhash: Hash) {
+        if state.latest.is_some_and(|latest| latest.slot >= slot) {
+            return;
+        }
+        state.latest = Some(RecentBlockhash {
+            slot,
+            blockhash,
+            updated_at: Instant::now(),
+        });
+        let oldest = slot.saturating_sub(MAX_PENDING_SLOTS);
+        state.blocks = state.blocks.split_off(&oldest);
+        state.committed = state.committed.split_off(&oldest);
+    }
+}
+
+#[async_trait]
+impl GeyserHandler for BlockhashService {
+    async fn slot(&self, slot: SlotUpdate) -> anyhow::Result<()> {
+        if !self.reached_commitment(slot.status) {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        match state.blocks.remove(&slot.slot) {
+            Some(blockhash) => Self::promote(&mut state, slot.slot, blockhash),
+            None => {
+                state.committed.insert(slot.slot);
+            }
+        }
+        Ok(())
+    }
+
+    async fn block_meta(&self, block_meta: BlockMetaUpdate) -> anyhow::Result<()> {
+        debug!(
+            "block {} at slot {} height {:?}",
+            block_meta.blockhash, block_meta.slot, block_meta.block_height
+        );
+        let mut state = self.state.lock().unwrap();
+        // block meta is only sent for complete blocks, which is all processed needs
+        if state.committed.remove(&block_meta.slot) || self.config.commitment == CommitmentLevel::Processed {
+            Self::promote(&mut state, block_meta.slot, block_meta.blockhash);
+        } else {
+            state.blocks.insert(block_meta.slot, block_meta.blockhash);
+        }
+        Ok(())
+    }
+}
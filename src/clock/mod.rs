@@ -0,0 +1,67 @@
+use crate::geyser::handler::{BlockMetaUpdate, GeyserHandler, SlotUpdate};
+use async_trait::async_trait;
+use solana_sdk::pubkey::Pubkey;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::debug;
+use yellowstone_grpc_proto::geyser::SlotStatus;
+
+/// Newest slot seen on the Geyser stream and the leader of the newest block, shared with `Bench` to stamp the slot
+/// every transaction is sent in.
+#[derive(Debug)]
+pub struct SlotClock {
+    // 0 before the first update
+    slot: AtomicU64,
+    leader: RwLock<Option<(u64, Pubkey)>>,
+}
+
+impl SlotClock {
+    pub fn new() -> Self {
+        SlotClock {
+            slot: AtomicU64::new(0),
+            leader: RwLock::new(None),
+        }
+    }
+
+    pub fn current(&self) -> Option<u64> {
+        match self.slot.load(Ordering::Relaxed) {
+            0 => None,
+            slot => Some(slot),
+        }
+    }
+
+    /// Slot and leader of the newest block seen, needs the blocks_meta subscription.
+    pub fn leader(&self) -> Option<(u64, Pubkey)> {
+        *self.leader.read().unwrap()
+    }
+}
+
+impl Default for SlotClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl GeyserHandler for SlotClock {
+    async fn slot(&self, slot: SlotUpdate) -> anyhow::Result<()> {
+        if slot.status == SlotStatus::SlotDead {
+            debug!("slot {} (parent {:?}) is dead", slot.slot, slot.parent);
+            return Ok(());
+        }
+        self.slot.fetch_max(slot.slot, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn block_meta(&self, block_meta: BlockMetaUpdate) -> anyhow::Result<()> {
+        self.slot.fetch_max(block_meta.slot, Ordering::Relaxed);
+        let Some(leader) = block_meta.leader else {
+            return Ok(());
+        };
+        let mut current = self.leader.write().unwrap();
+        if current.is_none_or(|(slot, _)| slot < block_meta.slot) {
+            *current = Some((block_meta.slot, leader));
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,20 @@
+use crate::tx_senders::constants::PUMP_FUN_PROGRAM_ADDR;
+use crate::tx_senders::jito::MAX_BUNDLE_SIZE;
+use crate::tx_senders::tip::TipAccounts;
+use anyhow::Context;
+use reqwest::Url;
+use reqwest::header::{HeaderName, HeaderValue};
 use serde::Deserialize;
+use solana_sdk::commitment_config::CommitmentLevel;
+use solana_sdk::pubkey::Pubkey;
 use std::collections::HashMap;
 use std::fs;
+use std::str::FromStr;
+
+// the percentiles the jito tip floor api reports
+pub const TIP_FLOOR_PERCENTILES: [u8; 5] = [25, 50, 75, 95, 99];
+// name `geyser_url` is raced under
+const DEFAULT_GEYSER_ENDPOINT: &str = "default";
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct PingThingsArgs {
@@ -8,14 +22,394 @@ pub struct PingThingsArgs {
     pub rpc: HashMap<String, RpcConfig>,
     pub http_rpc: String,
     pub ws_rpc: String,
-    pub geyser_url: String,
-    pub geyser_x_token: String,
+    // single endpoint, kept next to `geyser` as the endpoint named "default"
+    #[serde(default)]
+    pub geyser_url: Option<String>,
+    #[serde(default)]
+    pub geyser_x_token: Option<String>,
+    // endpoint_name -> endpoint, all of them are subscribed and raced
+    #[serde(default)]
+    pub geyser: HashMap<String, GeyserEndpointConfig>,
+    #[serde(default)]
+    pub subscription: SubscriptionConfig,
+    #[serde(default)]
+    pub geyser_reconnect: GeyserReconnectConfig,
+    #[serde(default)]
+    pub geyser_keepalive: GeyserKeepaliveConfig,
+    #[serde(default)]
+    pub handler_pipeline: HandlerPipelineConfig,
+    #[serde(default)]
+    pub recording: RecordingConfig,
+    #[serde(default)]
+    pub slot_clock: SlotClockConfig,
+    #[serde(default)]
+    pub blockhash: BlockhashConfig,
+    #[serde(default)]
+    pub bonding_curve_accounts: BondingCurveAccountsConfig,
+    #[serde(default)]
+    pub instruction_decoding: InstructionDecoding,
     pub private_key: String,
     pub compute_unit_price: u64,
     pub compute_unit_limit: u32,
     pub tip: f64,
     pub buy_amount: f64,
     pub min_amount_out: f64,
+    #[serde(default)]
+    pub confirmation: ConfirmationConfig,
+    // fetched from the block engine with getTipAccounts and refreshed periodically when not set
+    #[serde(default)]
+    pub tip_accounts: Option<Vec<String>>,
+    #[serde(default)]
+    pub tip_account_selection: TipAccountSelection,
+    #[serde(default)]
+    pub tip_strategy: TipStrategyConfig,
+    #[serde(default)]
+    pub priority_fee: PriorityFeeConfig,
+    #[serde(default)]
+    pub compute_units: ComputeUnitConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ComputeUnitConfig {
+    // simulate the first transaction of each shape and use what it consumed instead of `compute_unit_limit`
+    pub auto: bool,
+    pub margin_percent: u32,
+    // a pump.fun token we do not hold, its buy is simulated at startup so the first real buy is already sized
+    pub warmup_mint: Option<String>,
+}
+
+impl Default for ComputeUnitConfig {
+    fn default() -> Self {
+        ComputeUnitConfig {
+            auto: false,
+            margin_percent: 10,
+            warmup_mint: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PriorityFeeMode {
+    // always `compute_unit_price`
+    #[default]
+    Fixed,
+    // a percentile of getRecentPrioritizationFees, `compute_unit_price` until the first refresh
+    Estimate,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PriorityFeeConfig {
+    pub mode: PriorityFeeMode,
+    pub percentile: u8,
+    // ceiling in micro-lamports per compute unit
+    pub max: u64,
+    pub refresh_ms: u64,
+    // how many recently created bonding curves to include in the estimate
+    pub bonding_curves: usize,
+}
+
+impl Default for PriorityFeeConfig {
+    fn default() -> Self {
+        PriorityFeeConfig {
+            mode: PriorityFeeMode::Fixed,
+            percentile: 75,
+            max: 50_000_000,
+            refresh_ms: 5_000,
+            bonding_curves: 32,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TipMode {
+    // always `tip`
+    #[default]
+    Fixed,
+    // a percentile of recently landed tips from the tip floor api, `tip` until the first refresh
+    Percentile,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TipStrategyConfig {
+    pub mode: TipMode,
+    // one of `TIP_FLOOR_PERCENTILES`
+    pub percentile: u8,
+    // in sol
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub refresh_ms: u64,
+    pub tip_floor_url: String,
+}
+
+impl Default for TipStrategyConfig {
+    fn default() -> Self {
+        TipStrategyConfig {
+            mode: TipMode::Fixed,
+            percentile: 75,
+            min: None,
+            max: None,
+            refresh_ms: 10_000,
+            tip_floor_url: "https://bundles.jito.wtf/api/v1/bundles/tip_floor".to_string(),
+        }
+    }
+}
+
+impl TipStrategyConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if !TIP_FLOOR_PERCENTILES.contains(&self.percentile) {
+            anyhow::bail!(
+                "tip_strategy.percentile must be one of {:?}, got {}",
+                TIP_FLOOR_PERCENTILES,
+                self.percentile
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TipAccountSelection {
+    #[default]
+    Random,
+    RoundRobin,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SubscriptionConfig {
+    pub commitment: CommitmentLevel,
+    // transactions mentioning any of these accounts
+    pub account_include: Vec<String>,
+    // but none of these
+    pub account_exclude: Vec<String>,
+    // and all of these
+    pub account_required: Vec<String>,
+    pub include_failed: bool,
+    pub include_votes: bool,
+}
+
+impl SubscriptionConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        for (field, accounts) in [
+            ("account_include", &self.account_include),
+            ("account_exclude", &self.account_exclude),
+            ("account_required", &self.account_required),
+        ] {
+            for account in accounts {
+                Pubkey::from_str(account).with_context(|| format!("subscription.{} has invalid {}", field, account))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        SubscriptionConfig {
+            commitment: CommitmentLevel::Processed,
+            account_include: vec![PUMP_FUN_PROGRAM_ADDR.to_string()],
+            account_exclude: vec![],
+            account_required: vec![],
+            include_failed: false,
+            include_votes: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GeyserEndpointConfig {
+    pub url: String,
+    #[serde(default)]
+    pub x_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GeyserReconnectConfig {
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    // consecutive failed attempts before giving up, retry forever when not set
+    pub max_retries: Option<u32>,
+}
+
+impl Default for GeyserReconnectConfig {
+    fn default() -> Self {
+        GeyserReconnectConfig {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 10_000,
+            max_retries: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GeyserKeepaliveConfig {
+    pub ping_interval_ms: u64,
+    // the stream is considered dead and reconnected when no pong arrives for this long
+    pub pong_timeout_ms: u64,
+}
+
+impl Default for GeyserKeepaliveConfig {
+    fn default() -> Self {
+        GeyserKeepaliveConfig {
+            ping_interval_ms: 10_000,
+            pong_timeout_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InstructionDecoding {
+    // reject transactions with out of range account indexes or addresses that do not match their lookup tables
+    #[default]
+    Strict,
+    // use the default pubkey for accounts that cannot be resolved
+    Lenient,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockhashSource {
+    // the recent blockhash of the create transaction we are reacting to
+    #[default]
+    Creator,
+    // the newest blockhash at `commitment`, from geyser block meta or getLatestBlockhash
+    Latest,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BlockhashConfig {
+    pub source: BlockhashSource,
+    pub commitment: CommitmentLevel,
+    // getLatestBlockhash is used when geyser did not deliver a blockhash for this long
+    pub max_age_ms: u64,
+}
+
+impl Default for BlockhashConfig {
+    fn default() -> Self {
+        BlockhashConfig {
+            source: BlockhashSource::Creator,
+            commitment: CommitmentLevel::Confirmed,
+            max_age_ms: 5_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SlotClockConfig {
+    // also follow first shred received, bank created, ... instead of only the commitment transitions
+    pub interslot_updates: bool,
+    // subscribe to block meta for the leader and blockhash of every block
+    pub blocks_meta: bool,
+}
+
+impl Default for SlotClockConfig {
+    fn default() -> Self {
+        SlotClockConfig {
+            interslot_updates: false,
+            blocks_meta: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BondingCurveSubscription {
+    #[default]
+    None,
+    // every pump.fun bonding curve
+    Owner,
+    // only the bonding curves of tokens we bought, added after each buy
+    Held,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BondingCurveAccountsConfig {
+    pub subscribe: BondingCurveSubscription,
+    // narrows `owner` down to accounts of exactly this size
+    pub data_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    // file every geyser update is written to
+    pub record: Option<String>,
+    // file replayed instead of connecting to geyser
+    pub replay: Option<String>,
+    // 1.0 replays at the recorded pace, 0 as fast as possible
+    pub replay_speed: f64,
+    // replays only log the buys they would send unless this is set
+    pub send: bool,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        RecordingConfig {
+            record: None,
+            replay: None,
+            replay_speed: 1.0,
+            send: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    // stop reading from geyser until a worker frees a spot
+    #[default]
+    Block,
+    // keep reading and discard the oldest queued update
+    DropOldest,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HandlerPipelineConfig {
+    // how many updates are handled concurrently, in no particular order
+    pub workers: usize,
+    pub queue_capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for HandlerPipelineConfig {
+    fn default() -> Self {
+        HandlerPipelineConfig {
+            workers: 4,
+            queue_capacity: 10_000,
+            overflow: OverflowPolicy::Block,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConfirmationConfig {
+    pub commitment: CommitmentLevel,
+    pub timeout_ms: u64,
+    pub poll_interval_ms: u64,
+}
+
+impl Default for ConfirmationConfig {
+    fn default() -> Self {
+        ConfirmationConfig {
+            commitment: CommitmentLevel::Confirmed,
+            timeout_ms: 60_000,
+            poll_interval_ms: 400,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Default, Clone)]
@@ -25,18 +419,276 @@ pub enum RpcType {
     SolanaRpc,
     Jito,
 }
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum JitoMode {
+    #[default]
+    Transaction,
+    Bundle,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleTx {
+    Buy,
+    Tip,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    // `auth_name: auth`
+    #[default]
+    Header,
+    // `Authorization: Bearer auth`
+    Bearer,
+    // `?auth_name=auth`
+    Query,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct RpcConfig {
     pub url: String,
     #[serde(default)]
     pub auth: Option<String>,
     #[serde(default)]
+    pub auth_mode: AuthMode,
+    // header or query param name, defaults to jito's `x-jito-auth` / `uuid` for jito senders and required otherwise
+    #[serde(default)]
+    pub auth_name: Option<String>,
+    #[serde(default)]
     pub rpc_type: RpcType,
+    // only read by jito senders
+    #[serde(default)]
+    pub jito_mode: JitoMode,
+    #[serde(default = "default_bundle")]
+    pub bundle: Vec<BundleTx>,
+}
+
+fn default_bundle() -> Vec<BundleTx> {
+    vec![BundleTx::Buy]
+}
+
+impl RpcConfig {
+    pub fn url_with_auth(&self) -> String {
+        let (Some(auth), AuthMode::Query, Some(name)) = (&self.auth, self.auth_mode, self.auth_name()) else {
+            return self.url.clone();
+        };
+        match Url::parse(&self.url) {
+            Ok(mut url) => {
+                url.query_pairs_mut().append_pair(name, auth);
+                url.to_string()
+            }
+            // rejected when the config loads
+            Err(_) => self.url.clone(),
+        }
+    }
+
+    /// Header carrying `auth`, none for query auth or anonymous endpoints.
+    pub fn auth_header(&self) -> Option<(String, String)> {
+        let auth = self.auth.as_ref()?;
+        match self.auth_mode {
+            AuthMode::Header => Some((self.auth_name()?.to_string(), auth.clone())),
+            AuthMode::Bearer => Some(("Authorization".to_string(), format!("Bearer {}", auth))),
+            AuthMode::Query => None,
+        }
+    }
+
+    // only the jito block engine has a well known name for its auth
+    fn auth_name(&self) -> Option<&str> {
+        match (&self.auth_name, &self.rpc_type, self.auth_mode) {
+            (Some(auth_name), _, _) => Some(auth_name),
+            (None, RpcType::Jito, AuthMode::Header) => Some("x-jito-auth"),
+            (None, RpcType::Jito, AuthMode::Query) => Some("uuid"),
+            _ => None,
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        Url::parse(&self.url).with_context(|| format!("invalid url {}", self.url))?;
+        if let Some((auth_name, auth)) = self.auth_header() {
+            HeaderName::from_str(&auth_name).with_context(|| format!("invalid auth_name {:?}", auth_name))?;
+            HeaderValue::from_str(&auth).context("auth is not a valid header value")?;
+        }
+        if self.auth.is_some() && !matches!(self.auth_mode, AuthMode::Bearer) && self.auth_name().is_none() {
+            anyhow::bail!(
+                "auth_name is required for {:?} auth of non jito senders",
+                self.auth_mode
+            );
+        }
+        if matches!((&self.rpc_type, self.jito_mode), (RpcType::Jito, JitoMode::Bundle)) {
+            self.validate_bundle()?;
+        }
+        Ok(())
+    }
+
+    // the block engine rejects bundles with the same transaction twice
+    fn validate_bundle(&self) -> anyhow::Result<()> {
+        if self.bundle.is_empty() || self.bundle.len() > MAX_BUNDLE_SIZE {
+            anyhow::bail!("bundle must have between 1 and {} transactions", MAX_BUNDLE_SIZE);
+        }
+        for (position, bundle_tx) in self.bundle.iter().enumerate() {
+            if self.bundle[..position].contains(bundle_tx) {
+                anyhow::bail!("bundle has {:?} more than once", bundle_tx);
+            }
+        }
+        if !self.bundle.contains(&BundleTx::Buy) {
+            anyhow::bail!("bundle has no buy");
+        }
+        Ok(())
+    }
 }
 
 impl PingThingsArgs {
-    pub fn new() -> Self {
-        let config_yaml = fs::read_to_string("./config.yaml").expect("cannot find config file");
-        serde_yaml::from_str::<PingThingsArgs>(&config_yaml).expect("invalid config file")
+    pub fn load() -> anyhow::Result<Self> {
+        let config_yaml = fs::read_to_string("./config.yaml").context("cannot find config file")?;
+        let config = serde_yaml::from_str::<PingThingsArgs>(&config_yaml).context("invalid config file")?;
+        config.validate().context("invalid config file")?;
+        Ok(config)
+    }
+
+    // what serde cannot check, so typos fail at startup instead of on every use
+    fn validate(&self) -> anyhow::Result<()> {
+        for (name, rpc) in &self.rpc {
+            rpc.validate().with_context(|| format!("rpc {}", name))?;
+        }
+        if self.geyser_url.is_some() && self.geyser.contains_key(DEFAULT_GEYSER_ENDPOINT) {
+            anyhow::bail!(
+                "geyser_url is raced as geyser endpoint {:?}, rename the one in geyser",
+                DEFAULT_GEYSER_ENDPOINT
+            );
+        }
+        self.subscription.validate()?;
+        TipAccounts::from_config(self.tip_accounts.as_ref(), self.tip_account_selection)?;
+        self.tip_strategy.validate()
+    }
+
+    pub fn geyser_endpoints(&self) -> HashMap<String, GeyserEndpointConfig> {
+        let mut endpoints = self.geyser.clone();
+        if let Some(url) = &self.geyser_url {
+            endpoints.insert(DEFAULT_GEYSER_ENDPOINT.to_string(), GeyserEndpointConfig {
+                url: url.clone(),
+                x_token: self.geyser_x_token.clone(),
+            });
+        }
+        endpoints
+    }
+
+    /// Replays never spend funds unless `recording.send` asks for it.
+    pub fn dry_run(&self) -> bool {
+        self.recording.replay.is_some() && !self.recording.send
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_config() -> PingThingsArgs {
+        serde_yaml::from_str(include_str!("../../config.example.yaml")).unwrap()
+    }
+
+    #[test]
+    fn example_config_is_valid() {
+        example_config().validate().unwrap();
+    }
+
+    #[test]
+    fn unknown_tip_percentile_is_rejected() {
+        let mut config = example_config();
+        config.tip_strategy.percentile = 90;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn geyser_url_cannot_shadow_a_default_endpoint() {
+        let mut config = example_config();
+        config.geyser_url = Some("https://example.com".to_string());
+        config.geyser.insert(DEFAULT_GEYSER_ENDPOINT.to_string(), GeyserEndpointConfig {
+            url: "https://example.org".to_string(),
+            x_token: None,
+        });
+        assert!(config.validate().is_err());
+        config.geyser_url = None;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_subscription_accounts_are_rejected() {
+        let mut config = example_config();
+        config.subscription.account_required = vec!["pump".to_string()];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn invalid_tip_accounts_are_rejected() {
+        let mut config = example_config();
+        config.tip_accounts = Some(vec!["not a pubkey".to_string()]);
+        assert!(config.validate().is_err());
+        config.tip_accounts = Some(vec![]);
+        assert!(config.validate().is_err());
+    }
+
+    fn rpc_config(rpc_type: RpcType, auth_mode: AuthMode, auth_name: Option<&str>) -> RpcConfig {
+        RpcConfig {
+            url: "https://example.com/api/v1/transactions?region=ny".to_string(),
+            auth: Some("a&b=c".to_string()),
+            auth_mode,
+            auth_name: auth_name.map(str::to_string),
+            rpc_type,
+            jito_mode: JitoMode::Transaction,
+            bundle: default_bundle(),
+        }
+    }
+
+    #[test]
+    fn query_auth_is_percent_encoded() {
+        let rpc = rpc_config(RpcType::Jito, AuthMode::Query, None);
+        assert_eq!(
+            rpc.url_with_auth(),
+            "https://example.com/api/v1/transactions?region=ny&uuid=a%26b%3Dc"
+        );
+        assert_eq!(rpc.auth_header(), None);
+    }
+
+    #[test]
+    fn auth_name_is_required_for_non_jito_senders() {
+        assert!(rpc_config(RpcType::SolanaRpc, AuthMode::Header, None).validate().is_err());
+        assert!(rpc_config(RpcType::SolanaRpc, AuthMode::Query, None).validate().is_err());
+        assert!(rpc_config(RpcType::SolanaRpc, AuthMode::Bearer, None).validate().is_ok());
+        let rpc = rpc_config(RpcType::SolanaRpc, AuthMode::Header, Some("x-api-key"));
+        assert!(rpc.validate().is_ok());
+        assert_eq!(rpc.auth_header(), Some(("x-api-key".to_string(), "a&b=c".to_string())));
+        let rpc = rpc_config(RpcType::Jito, AuthMode::Header, None);
+        assert!(rpc.validate().is_ok());
+        assert_eq!(
+            rpc.auth_header(),
+            Some(("x-jito-auth".to_string(), "a&b=c".to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid_auth_headers_are_rejected() {
+        assert!(rpc_config(RpcType::SolanaRpc, AuthMode::Header, Some("x api key")).validate().is_err());
+        let mut rpc = rpc_config(RpcType::SolanaRpc, AuthMode::Bearer, None);
+        rpc.auth = Some("token\n".to_string());
+        assert!(rpc.validate().is_err());
+    }
+
+    #[test]
+    fn duplicate_bundle_entries_are_rejected() {
+        let mut rpc = rpc_config(RpcType::Jito, AuthMode::Header, None);
+        rpc.jito_mode = JitoMode::Bundle;
+        for (bundle, valid) in [
+            (vec![BundleTx::Buy], true),
+            (vec![BundleTx::Tip, BundleTx::Buy], true),
+            (vec![BundleTx::Buy, BundleTx::Buy], false),
+            (vec![BundleTx::Buy, BundleTx::Tip, BundleTx::Tip], false),
+            (vec![BundleTx::Tip], false),
+            (vec![], false),
+        ] {
+            rpc.bundle = bundle;
+            assert_eq!(rpc.validate().is_ok(), valid, "{:?}", rpc.bundle);
+        }
     }
 }
@@ -0,0 +1,212 @@
+use crate::config::ConfirmationConfig;
+use crate::confirmation::{Confirmation, LandingStatus, PendingConfirmation, resolve_expired};
+use crate::tx_senders::jito::{
+    JitoBundleStatusResponse, JitoBundleStatusResponseInnerValue, JitoInflightBundleStatus,
+    JitoInflightBundleStatusResponse, JitoInflightBundleStatusResponseInnerValue,
+};
+use anyhow::Context;
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use solana_sdk::commitment_config::CommitmentLevel;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error};
+
+// the block engine rejects status queries for more than five bundles at once
+pub const MAX_BUNDLE_STATUSES_QUERY_ITEMS: usize = 5;
+
+/// Handle to the background task that resolves Jito bundle ids to landed/failed/invalid using
+/// `getInflightBundleStatuses`, then `getBundleStatuses` once a bundle has landed.
+#[derive(Clone)]
+pub struct BundleStatusPoller {
+    sender: mpsc::Sender<(String, oneshot::Sender<Confirmation>)>,
+}
+
+impl BundleStatusPoller {
+    pub fn spawn(
+        url: String,
+        auth_header: Option<(String, String)>,
+        client: Client,
+        config: ConfirmationConfig,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(100);
+        let client = BlockEngineClient {
+            url,
+            auth_header,
+            client,
+        };
+        tokio::spawn(Self::run(client, config, receiver));
+        BundleStatusPoller { sender }
+    }
+
+    pub async fn confirm(&self, bundle_id: String) -> anyhow::Result<Confirmation> {
+        let (reply, response) = oneshot::channel();
+        self.sender.send((bundle_id, reply)).await.map_err(|_| anyhow::anyhow!("bundle status poller is gone"))?;
+        Ok(response.await?)
+    }
+
+    async fn run(
+        client: BlockEngineClient,
+        config: ConfirmationConfig,
+        mut receiver: mpsc::Receiver<(String, oneshot::Sender<Confirmation>)>,
+    ) {
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let mut interval = tokio::time::interval(Duration::from_millis(config.poll_interval_ms));
+        let mut pending = HashMap::<String, PendingConfirmation>::new();
+
+        loop {
+            if pending.is_empty() {
+                let Some((bundle_id, reply)) = receiver.recv().await else {
+                    return;
+                };
+                pending.insert(bundle_id, PendingConfirmation::new(timeout, reply));
+            }
+            while let Ok((bundle_id, reply)) = receiver.try_recv() {
+                pending.insert(bundle_id, PendingConfirmation::new(timeout, reply));
+            }
+
+            interval.tick().await;
+
+            let bundle_ids = pending.keys().cloned().collect::<Vec<String>>();
+            let mut landed = Vec::<String>::new();
+            for chunk in bundle_ids.chunks(MAX_BUNDLE_STATUSES_QUERY_ITEMS) {
+                let statuses = match client.inflight_bundle_statuses(chunk).await {
+                    Ok(statuses) => statuses,
+                    Err(e) => {
+                        error!("cannot fetch inflight bundle statuses: {:?}", e);
+                        continue;
+                    }
+                };
+
+                for status in statuses {
+                    let landing_status = match status.status {
+                        JitoInflightBundleStatus::Pending => continue,
+                        JitoInflightBundleStatus::Landed => {
+                            if let Some(entry) = pending.get_mut(&status.bundle_id) {
+                                entry.last_slot = status.landed_slot;
+                            }
+                            landed.push(status.bundle_id);
+                            continue;
+                        }
+                        JitoInflightBundleStatus::Failed => LandingStatus::Failed,
+                        JitoInflightBundleStatus::Invalid => LandingStatus::Invalid,
+                    };
+
+                    debug!("bundle {} resolved: {:?}", status.bundle_id, landing_status);
+                    if let Some(entry) = pending.remove(&status.bundle_id) {
+                        entry.resolve(landing_status, None);
+                    }
+                }
+            }
+
+            for chunk in landed.chunks(MAX_BUNDLE_STATUSES_QUERY_ITEMS) {
+                let statuses = match client.bundle_statuses(chunk).await {
+                    Ok(statuses) => statuses,
+                    Err(e) => {
+                        error!("cannot fetch bundle statuses: {:?}", e);
+                        continue;
+                    }
+                };
+
+                for status in statuses.into_iter().flatten() {
+                    let Some(entry) = pending.get_mut(&status.bundle_id) else {
+                        continue;
+                    };
+                    entry.last_slot = Some(status.slot);
+                    entry.last_confirmation_status = Some(status.confirmation_status.clone());
+
+                    let err = bundle_err(&status.err);
+                    let landing_status = if err.is_some() {
+                        LandingStatus::Failed
+                    } else if commitment_reached(&status.confirmation_status, config.commitment) {
+                        LandingStatus::Landed
+                    } else {
+                        continue;
+                    };
+
+                    debug!(
+                        "bundle {} resolved: {:?} {:?}",
+                        status.bundle_id, landing_status, status.transactions
+                    );
+                    if let Some(entry) = pending.remove(&status.bundle_id) {
+                        entry.resolve(landing_status, err);
+                    }
+                }
+            }
+
+            resolve_expired(&mut pending);
+        }
+    }
+}
+
+struct BlockEngineClient {
+    url: String,
+    auth_header: Option<(String, String)>,
+    client: Client,
+}
+
+impl BlockEngineClient {
+    async fn inflight_bundle_statuses(
+        &self,
+        bundle_ids: &[String],
+    ) -> anyhow::Result<Vec<JitoInflightBundleStatusResponseInnerValue>> {
+        let response: JitoInflightBundleStatusResponse = self.call("getInflightBundleStatuses", bundle_ids).await?;
+        debug!("inflight bundle statuses at slot {}", response.result.context.slot);
+        Ok(response.result.value)
+    }
+
+    async fn bundle_statuses(
+        &self,
+        bundle_ids: &[String],
+    ) -> anyhow::Result<Vec<Option<JitoBundleStatusResponseInnerValue>>> {
+        let response: JitoBundleStatusResponse = self.call("getBundleStatuses", bundle_ids).await?;
+        debug!("bundle statuses at slot {}", response.result.context.slot);
+        Ok(response.result.value)
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, bundle_ids: &[String]) -> anyhow::Result<T> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": [bundle_ids]
+        });
+        let mut request = self.client.post(&self.url).json(&body);
+        if let Some((auth_name, auth)) = &self.auth_header {
+            request = request.header(auth_name, auth);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("{} failed: {}", method, body));
+        }
+        serde_json::from_str::<T>(&body).context(format!("cannot deserialize {} response", method))
+    }
+}
+
+// `err` is `{"Ok": null}` for bundles that executed cleanly
+fn bundle_err(err: &Value) -> Option<String> {
+    match err.get("Ok") {
+        Some(_) => None,
+        None if err.is_null() => None,
+        None => Some(err.to_string()),
+    }
+}
+
+fn commitment_reached(confirmation_status: &str, commitment: CommitmentLevel) -> bool {
+    let rank = |commitment: CommitmentLevel| match commitment {
+        CommitmentLevel::Processed => 0,
+        CommitmentLevel::Confirmed => 1,
+        CommitmentLevel::Finalized => 2,
+    };
+    let reached = match confirmation_status {
+        "processed" => CommitmentLevel::Processed,
+        "confirmed" => CommitmentLevel::Confirmed,
+        "finalized" => CommitmentLevel::Finalized,
+        _ => return false,
+    };
+    rank(reached) >= rank(commitment)
+}
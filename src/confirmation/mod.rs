@@ -0,0 +1,160 @@
+use crate::config::ConfirmationConfig;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_request::MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::Signature;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tracing::{debug, error};
+
+pub mod bundles;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LandingStatus {
+    Landed,
+    Failed,
+    Invalid,
+    TimedOut,
+}
+
+#[derive(Debug, Clone)]
+pub struct Confirmation {
+    pub status: LandingStatus,
+    pub slot: Option<u64>,
+    pub confirmation_status: Option<String>,
+    pub err: Option<String>,
+}
+
+impl Confirmation {
+    pub fn is_success(&self) -> bool {
+        self.status == LandingStatus::Landed
+    }
+}
+
+struct PendingConfirmation {
+    deadline: Instant,
+    last_slot: Option<u64>,
+    last_confirmation_status: Option<String>,
+    reply: oneshot::Sender<Confirmation>,
+}
+
+/// Handle to the background task that polls `getSignatureStatuses` for every tracked signature until it reaches
+/// the configured commitment or times out.
+#[derive(Clone)]
+pub struct SignatureTracker {
+    sender: mpsc::Sender<(Signature, oneshot::Sender<Confirmation>)>,
+}
+
+impl SignatureTracker {
+    pub fn spawn(http_rpc: Arc<RpcClient>, config: ConfirmationConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(100);
+        tokio::spawn(Self::run(http_rpc, config, receiver));
+        SignatureTracker { sender }
+    }
+
+    pub async fn confirm(&self, signature: Signature) -> anyhow::Result<Confirmation> {
+        let (reply, response) = oneshot::channel();
+        self.sender.send((signature, reply)).await.map_err(|_| anyhow::anyhow!("signature tracker is gone"))?;
+        Ok(response.await?)
+    }
+
+    async fn run(
+        http_rpc: Arc<RpcClient>,
+        config: ConfirmationConfig,
+        mut receiver: mpsc::Receiver<(Signature, oneshot::Sender<Confirmation>)>,
+    ) {
+        let commitment = CommitmentConfig {
+            commitment: config.commitment,
+        };
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let mut interval = tokio::time::interval(Duration::from_millis(config.poll_interval_ms));
+        let mut pending = HashMap::<Signature, PendingConfirmation>::new();
+
+        loop {
+            // block on the channel while idle so the rpc isn't polled for nothing
+            if pending.is_empty() {
+                let Some((signature, reply)) = receiver.recv().await else {
+                    return;
+                };
+                pending.insert(signature, PendingConfirmation::new(timeout, reply));
+            }
+            while let Ok((signature, reply)) = receiver.try_recv() {
+                pending.insert(signature, PendingConfirmation::new(timeout, reply));
+            }
+
+            interval.tick().await;
+
+            let signatures = pending.keys().copied().collect::<Vec<Signature>>();
+            for chunk in signatures.chunks(MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS) {
+                let statuses = match http_rpc.get_signature_statuses(chunk).await {
+                    Ok(response) => response.value,
+                    Err(e) => {
+                        error!("cannot fetch signature statuses: {:?}", e);
+                        continue;
+                    }
+                };
+
+                for (signature, status) in chunk.iter().zip(statuses) {
+                    let Some(status) = status else {
+                        continue;
+                    };
+                    let Some(entry) = pending.get_mut(signature) else {
+                        continue;
+                    };
+                    entry.last_slot = Some(status.slot);
+                    entry.last_confirmation_status = status.confirmation_status.as_ref().map(|s| format!("{:?}", s));
+
+                    let landing_status = if status.err.is_some() {
+                        LandingStatus::Failed
+                    } else if status.satisfies_commitment(commitment) {
+                        LandingStatus::Landed
+                    } else {
+                        continue;
+                    };
+
+                    debug!("signature {} resolved: {:?}", signature, landing_status);
+                    if let Some(entry) = pending.remove(signature) {
+                        entry.resolve(landing_status, status.err.map(|err| err.to_string()));
+                    }
+                }
+            }
+
+            resolve_expired(&mut pending);
+        }
+    }
+}
+
+impl PendingConfirmation {
+    fn new(timeout: Duration, reply: oneshot::Sender<Confirmation>) -> Self {
+        PendingConfirmation {
+            deadline: Instant::now() + timeout,
+            last_slot: None,
+            last_confirmation_status: None,
+            reply,
+        }
+    }
+
+    fn resolve(self, status: LandingStatus, err: Option<String>) {
+        let _ = self.reply.send(Confirmation {
+            status,
+            slot: self.last_slot,
+            confirmation_status: self.last_confirmation_status,
+            err,
+        });
+    }
+}
+
+fn resolve_expired<K: Clone + Eq + Hash>(pending: &mut HashMap<K, PendingConfirmation>) {
+    let now = Instant::now();
+    let expired =
+        pending.iter().filter(|(_, entry)| entry.deadline <= now).map(|(key, _)| key.clone()).collect::<Vec<K>>();
+    for key in expired {
+        if let Some(entry) = pending.remove(&key) {
+            entry.resolve(LandingStatus::TimedOut, None);
+        }
+    }
+}
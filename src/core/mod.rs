@@ -1,86 +1,554 @@
-use crate::geyser::GeyserResult;
-use solana_sdk::instruction::AccountMeta;
-use solana_sdk::message::VersionedMessage;
-use solana_sdk::message::v0::{LoadedAddresses, LoadedMessage};
+use crate::config::InstructionDecoding;
+use solana_sdk::instruction::{AccountMeta, CompiledInstruction, Instruction};
+use solana_sdk::message::v0::{self, LoadedAddresses};
+use solana_sdk::message::{AccountKeys, VersionedMessage};
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::reserved_account_keys::ReservedAccountKeys;
 use solana_sdk::transaction::VersionedTransaction;
-use solana_transaction_status::TransactionStatusMeta;
+use solana_transaction_status::{InnerInstruction, InnerInstructions, TransactionStatusMeta};
+use thiserror::Error;
 
+// stack height of the instructions in the transaction message
+const TOP_LEVEL_STACK_HEIGHT: u32 = 1;
+// deepest invocation the runtime allows, the top level instruction included
+const MAX_STACK_HEIGHT: usize = 5;
+
+/// An instruction of a transaction, either from the message or invoked through CPI.
+#[derive(Debug, Clone)]
+pub struct FlattenedInstruction {
+    pub instruction: Instruction,
+    // 1 for instructions of the message, 2 for the ones they invoke and so on
+    pub stack_height: u32,
+    // index of the invoking instruction in the flattened list, None for instructions of the message
+    pub parent_index: Option<usize>,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("account index {index} is out of range of the {len} account keys")]
+    MissingKey { index: u8, len: usize },
+    #[error("instruction has {len} accounts, account {position} was asked for")]
+    MissingAccount { position: usize, len: usize },
+    #[error("lookup tables select {expected} {kind} addresses but {loaded} were loaded")]
+    LookupTableLoad {
+        kind: &'static str,
+        expected: usize,
+        loaded: usize,
+    },
+    #[error("malformed message: {0}")]
+    MalformedMessage(String),
+}
+
+pub type CoreResult<T> = Result<T, Error>;
+
+/// Returns the instructions of the message, each followed by the inner instructions it invoked, in execution order.
+/// With `InstructionDecoding::Strict` a malformed transaction is an error instead of instructions with default pubkeys.
 pub fn extract_instructions(
-    meta_data: TransactionStatusMeta,
-    transaction: VersionedTransaction,
-) -> GeyserResult<Vec<solana_sdk::instruction::Instruction>> {
-    let message = transaction.message.clone();
-    let meta = meta_data.clone();
-
-    let mut instructions = Vec::<solana_sdk::instruction::Instruction>::new();
-
-    match message {
-        VersionedMessage::Legacy(legacy) => {
-            for (_i, compiled_instruction) in legacy.instructions.iter().enumerate() {
-                let program_id = *legacy
-                    .account_keys
-                    .get(compiled_instruction.program_id_index as usize)
-                    .unwrap_or(&Pubkey::default());
-
-                let accounts: Vec<_> = compiled_instruction
-                    .accounts
-                    .iter()
-                    .filter_map(|account_index| {
-                        let account_pubkey = legacy.account_keys.get(*account_index as usize)?;
-                        Some(AccountMeta {
-                            pubkey: *account_pubkey,
-                            is_writable: legacy.is_maybe_writable(*account_index as usize, None),
-                            is_signer: legacy.is_signer(*account_index as usize),
-                        })
-                    })
-                    .collect();
-
-                instructions.push(solana_sdk::instruction::Instruction {
-                    program_id,
-                    accounts,
-                    data: compiled_instruction.data.clone(),
-                });
+    meta: &TransactionStatusMeta,
+    transaction: &VersionedTransaction,
+    decoding: InstructionDecoding,
+) -> CoreResult<Vec<FlattenedInstruction>> {
+    TransactionInstructions::new(meta, transaction, decoding)?
+        .iter()
+        .map(|instruction| instruction.to_flattened())
+        .collect()
+}
+
+/// The instructions of a transaction borrowed from its message and metadata, in the same order as
+/// `extract_instructions`. Nothing is resolved or copied until asked for, so callers can skip the instructions of
+/// programs they do not care about for free.
+pub struct TransactionInstructions<'a> {
+    message: &'a VersionedMessage,
+    account_keys: AccountKeys<'a>,
+    inner_instructions: &'a [InnerInstructions],
+    decoding: InstructionDecoding,
+}
+
+impl<'a> TransactionInstructions<'a> {
+    pub fn new(
+        meta: &'a TransactionStatusMeta,
+        transaction: &'a VersionedTransaction,
+        decoding: InstructionDecoding,
+    ) -> CoreResult<Self> {
+        let message = &transaction.message;
+        let inner_instructions = meta.inner_instructions.as_deref().unwrap_or_default();
+
+        let loaded_addresses = match message {
+            VersionedMessage::Legacy(_) => None,
+            VersionedMessage::V0(_) => Some(&meta.loaded_addresses),
+        };
+
+        if decoding == InstructionDecoding::Strict {
+            message.sanitize().map_err(|err| Error::MalformedMessage(err.to_string()))?;
+            if let VersionedMessage::V0(v0) = message {
+                check_loaded_addresses(v0, &meta.loaded_addresses)?;
+            }
+            let top_level = message.instructions().len();
+            if let Some(inner) = inner_instructions.iter().find(|inner| inner.index as usize >= top_level) {
+                return Err(Error::MalformedMessage(format!(
+                    "inner instructions of instruction {} but the message has {}",
+                    inner.index, top_level
+                )));
             }
         }
-        VersionedMessage::V0(v0) => {
-            let loaded_addresses = LoadedAddresses {
-                writable: meta.loaded_addresses.writable.iter().map(|key| key.clone()).collect(),
-                readonly: meta.loaded_addresses.readonly.iter().map(|key| key.clone()).collect(),
-            };
-
-            let loaded_message =
-                LoadedMessage::new(v0.clone(), loaded_addresses, &ReservedAccountKeys::empty_key_set());
-
-            for (_i, compiled_instruction) in v0.instructions.iter().enumerate() {
-                let program_id = *loaded_message
-                    .account_keys()
-                    .get(compiled_instruction.program_id_index as usize)
-                    .unwrap_or(&Pubkey::default());
-
-                let accounts: Vec<AccountMeta> = compiled_instruction
-                    .accounts
+
+        Ok(TransactionInstructions {
+            message,
+            account_keys: AccountKeys::new(message.static_account_keys(), loaded_addresses),
+            inner_instructions,
+            decoding,
+        })
+    }
+
+    pub fn iter(&self) -> InstructionIter<'_> {
+        InstructionIter {
+            instructions: self,
+            top_level: self.message.instructions().iter().enumerate(),
+            inner: [].iter(),
+            index: 0,
+            parents: [0; MAX_STACK_HEIGHT],
+        }
+    }
+
+    /// Instructions whose program id resolves to `program_id`, unresolvable ones are skipped.
+    pub fn for_program(&self, program_id: Pubkey) -> impl Iterator<Item = InstructionRef<'_>> {
+        self.iter().filter(move |instruction| instruction.program_id().is_ok_and(|id| id == program_id))
+    }
+
+    fn resolve_key(&self, index: u8) -> CoreResult<Pubkey> {
+        match self.account_keys.get(index as usize) {
+            Some(pubkey) => Ok(*pubkey),
+            None if self.decoding == InstructionDecoding::Lenient => Ok(Pubkey::default()),
+            None => Err(Error::MissingKey {
+                index,
+                len: self.account_keys.len(),
+            }),
+        }
+    }
+}
+
+// the lookup tables of the message must have loaded exactly the addresses the message selects from them, otherwise
+// every index into the loaded addresses points at the wrong account
+fn check_loaded_addresses(v0: &v0::Message, loaded_addresses: &LoadedAddresses) -> CoreResult<()> {
+    let writable = v0.address_table_lookups.iter().map(|lookup| lookup.writable_indexes.len()).sum::<usize>();
+    if writable != loaded_addresses.writable.len() {
+        return Err(Error::LookupTableLoad {
+            kind: "writable",
+            expected: writable,
+            loaded: loaded_addresses.writable.len(),
+        });
+    }
+    let readonly = v0.address_table_lookups.iter().map(|lookup| lookup.readonly_indexes.len()).sum::<usize>();
+    if readonly != loaded_addresses.readonly.len() {
+        return Err(Error::LookupTableLoad {
+            kind: "readonly",
+            expected: readonly,
+            loaded: loaded_addresses.readonly.len(),
+        });
+    }
+    Ok(())
+}
+
+pub struct InstructionIter<'a> {
+    instructions: &'a TransactionInstructions<'a>,
+    top_level: std::iter::Enumerate<std::slice::Iter<'a, CompiledInstruction>>,
+    // inner instructions of the last top level instruction not returned yet
+    inner: std::slice::Iter<'a, InnerInstruction>,
+    // index of the next instruction in the flattened list
+    index: usize,
+    // index of the last instruction returned at every stack height, the parents of the next inner instruction
+    parents: [usize; MAX_STACK_HEIGHT],
+}
+
+impl<'a> Iterator for InstructionIter<'a> {
+    type Item = InstructionRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.index;
+        let (compiled, stack_height, parent_index) = match self.inner.next() {
+            Some(inner_instruction) => {
+                // old metadata has no stack height, treat those as invoked by the top level instruction
+                let stack_height = inner_instruction
+                    .stack_height
+                    .unwrap_or(TOP_LEVEL_STACK_HEIGHT + 1)
+                    .clamp(TOP_LEVEL_STACK_HEIGHT + 1, MAX_STACK_HEIGHT as u32);
+                let parent_index = self.parents[stack_height as usize - 2];
+                (&inner_instruction.instruction, stack_height, Some(parent_index))
+            }
+            None => {
+                let (position, compiled) = self.top_level.next()?;
+                self.inner = self
+                    .instructions
+                    .inner_instructions
                     .iter()
-                    .filter_map(|account_index| {
-                        let account_pubkey = loaded_message.account_keys().get(*account_index as usize);
-
-                        return Some(AccountMeta {
-                            pubkey: account_pubkey.map(|acc| acc.clone()).unwrap_or_default(),
-                            is_writable: loaded_message.is_writable(*account_index as usize),
-                            is_signer: loaded_message.is_signer(*account_index as usize),
-                        });
-                    })
-                    .collect();
-
-                instructions.push(solana_sdk::instruction::Instruction {
-                    program_id,
-                    accounts,
-                    data: compiled_instruction.data.clone(),
-                });
+                    .find(|inner| inner.index as usize == position)
+                    .map(|inner| inner.instructions.iter())
+                    .unwrap_or_default();
+                (compiled, TOP_LEVEL_STACK_HEIGHT, None)
             }
+        };
+        self.parents[stack_height as usize - 1] = index;
+        self.index += 1;
+
+        Some(InstructionRef {
+            instructions: self.instructions,
+            compiled,
+            stack_height,
+            parent_index,
+        })
+    }
+}
+
+/// An instruction borrowed from `TransactionInstructions`, see `FlattenedInstruction` for the fields.
+#[derive(Clone, Copy)]
+pub struct InstructionRef<'a> {
+    instructions: &'a TransactionInstructions<'a>,
+    compiled: &'a CompiledInstruction,
+    pub stack_height: u32,
+    pub parent_index: Option<usize>,
+}
+
+impl<'a> InstructionRef<'a> {
+    pub fn program_id(&self) -> CoreResult<Pubkey> {
+        self.instructions.resolve_key(self.compiled.program_id_index)
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        &self.compiled.data
+    }
+
+    pub fn accounts_len(&self) -> usize {
+        self.compiled.accounts.len()
+    }
+
+    /// Pubkey of the account at `position` in the accounts of the instruction.
+    pub fn account(&self, position: usize) -> CoreResult<Pubkey> {
+        let index = self.compiled.accounts.get(position).ok_or(Error::MissingAccount {
+            position,
+            len: self.compiled.accounts.len(),
+        })?;
+        self.instructions.resolve_key(*index)
+    }
+
+    pub fn to_instruction(&self) -> CoreResult<Instruction> {
+        let message = self.instructions.message;
+        let accounts = self
+            .compiled
+            .accounts
+            .iter()
+            .map(|account_index| {
+                Ok(AccountMeta {
+                    pubkey: self.instructions.resolve_key(*account_index)?,
+                    is_writable: message.is_maybe_writable(*account_index as usize, None),
+                    is_signer: message.is_signer(*account_index as usize),
+                })
+            })
+            .collect::<CoreResult<Vec<_>>>()?;
+
+        Ok(Instruction {
+            program_id: self.program_id()?,
+            accounts,
+            data: self.compiled.data.clone(),
+        })
+    }
+
+    pub fn to_flattened(&self) -> CoreResult<FlattenedInstruction> {
+        Ok(FlattenedInstruction {
+            instruction: self.to_instruction()?,
+            stack_height: self.stack_height,
+            parent_index: self.parent_index,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::hash::Hash;
+    use solana_sdk::message::v0::MessageAddressTableLookup;
+    use solana_sdk::message::{MessageHeader, legacy};
+    use solana_sdk::signature::Signature;
+
+    fn compiled(program_id_index: u8, accounts: &[u8], data: &[u8]) -> CompiledInstruction {
+        CompiledInstruction::new_from_raw_parts(program_id_index, data.to_vec(), accounts.to_vec())
+    }
+
+    // inner instructions of the message instruction at `index`, with their stack heights
+    fn inner(index: u8, instructions: Vec<(CompiledInstruction, Option<u32>)>) -> InnerInstructions {
+        InnerInstructions {
+            index,
+            instructions: instructions
+                .into_iter()
+                .map(|(instruction, stack_height)| InnerInstruction {
+                    instruction,
+                    stack_height,
+                })
+                .collect(),
         }
     }
 
-    Ok(instructions)
+    // legacy transaction signed by the first of `account_keys`, the last `readonly` of them are readonly
+    fn legacy_transaction(
+        account_keys: &[Pubkey],
+        readonly: u8,
+        instructions: Vec<CompiledInstruction>,
+        inner_instructions: Vec<InnerInstructions>,
+    ) -> (TransactionStatusMeta, VersionedTransaction) {
+        let message = legacy::Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: readonly,
+            },
+            account_keys: account_keys.to_vec(),
+            recent_blockhash: Hash::new_unique(),
+            instructions,
+        };
+        let meta = TransactionStatusMeta {
+            inner_instructions: Some(inner_instructions),
+            ..TransactionStatusMeta::default()
+        };
+        let transaction = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::Legacy(message),
+        };
+        (meta, transaction)
+    }
+
+    fn flatten(meta: &TransactionStatusMeta, transaction: &VersionedTransaction) -> Vec<FlattenedInstruction> {
+        extract_instructions(meta, transaction, InstructionDecoding::Strict).unwrap()
+    }
+
+    fn shape(instructions: &[FlattenedInstruction]) -> Vec<(u32, Option<usize>)> {
+        instructions.iter().map(|instruction| (instruction.stack_height, instruction.parent_index)).collect()
+    }
+
+    #[test]
+    fn inner_instructions_follow_their_parent() {
+        let [payer, router, program] = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+        // the router calls the program, which calls itself
+        let (meta, transaction) = legacy_transaction(
+            &[payer, router, program],
+            2,
+            vec![compiled(1, &[0], &[1]), compiled(1, &[0], &[2])],
+            vec![inner(1, vec![
+                (compiled(2, &[0], &[3]), Some(2)),
+                (compiled(2, &[], &[4]), Some(3)),
+            ])],
+        );
+
+        let instructions = flatten(&meta, &transaction);
+        assert_eq!(shape(&instructions), vec![
+            (1, None),
+            (1, None),
+            (2, Some(1)),
+            (3, Some(2))
+        ]);
+        let programs: Vec<(Pubkey, u8)> = instructions
+            .iter()
+            .map(|instruction| (instruction.instruction.program_id, instruction.instruction.data[0]))
+            .collect();
+        assert_eq!(programs, vec![(router, 1), (router, 2), (program, 3), (program, 4)]);
+    }
+
+    #[test]
+    fn parent_index_returns_to_the_caller() {
+        let [payer, router, program] = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+        // after the nested call returns the router calls again, then once more from metadata without stack height
+        let (meta, transaction) = legacy_transaction(
+            &[payer, router, program],
+            2,
+            vec![compiled(1, &[0], &[1]), compiled(2, &[0], &[2])],
+            vec![inner(0, vec![
+                (compiled(2, &[0], &[3]), Some(2)),
+                (compiled(2, &[], &[4]), Some(3)),
+                (compiled(2, &[0], &[5]), Some(2)),
+                (compiled(2, &[0], &[6]), None),
+            ])],
+        );
+
+        let instructions = flatten(&meta, &transaction);
+        assert_eq!(shape(&instructions), vec![
+            (1, None),
+            (2, Some(0)),
+            (3, Some(1)),
+            (2, Some(0)),
+            (2, Some(0)),
+            (1, None)
+        ]);
+        let data: Vec<u8> = instructions.iter().map(|instruction| instruction.instruction.data[0]).collect();
+        assert_eq!(data, vec![1, 3, 4, 5, 6, 2]);
+    }
+
+    #[test]
+    fn lookup_table_accounts_resolve_to_loaded_addresses() {
+        let [payer, program, writable, readonly] = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        // account indexes 2 and 3 are the addresses loaded from the table
+        let message = v0::Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 1,
+            },
+            account_keys: vec![payer, program],
+            recent_blockhash: Hash::new_unique(),
+            instructions: vec![compiled(1, &[0, 2, 3], &[1])],
+            address_table_lookups: vec![MessageAddressTableLookup {
+                account_key: Pubkey::new_unique(),
+                writable_indexes: vec![4],
+                readonly_indexes: vec![7],
+            }],
+        };
+        let meta = TransactionStatusMeta {
+            inner_instructions: Some(vec![inner(0, vec![(compiled(1, &[3, 2], &[2]), Some(2))])]),
+            loaded_addresses: LoadedAddresses {
+                writable: vec![writable],
+                readonly: vec![readonly],
+            },
+            ..TransactionStatusMeta::default()
+        };
+        let transaction = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::V0(message),
+        };
+
+        let instructions = flatten(&meta, &transaction);
+        assert_eq!(instructions[0].instruction.accounts, vec![
+            AccountMeta::new(payer, true),
+            AccountMeta::new(writable, false),
+            AccountMeta::new_readonly(readonly, false),
+        ]);
+        assert_eq!(instructions[1].instruction.program_id, program);
+        assert_eq!(instructions[1].instruction.accounts, vec![
+            AccountMeta::new_readonly(readonly, false),
+            AccountMeta::new(writable, false),
+        ]);
+    }
+
+    #[test]
+    fn for_program_yields_the_instructions_of_the_program() {
+        let [payer, router, program, account] = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        // the program is called by the router and from the message, the router also calls itself
+        let (meta, transaction) = legacy_transaction(
+            &[payer, account, router, program],
+            2,
+            vec![compiled(2, &[0, 1], &[1]), compiled(3, &[1], &[2])],
+            vec![inner(0, vec![
+                (compiled(2, &[1], &[3]), Some(2)),
+                (compiled(3, &[1, 0], &[4]), Some(3)),
+            ])],
+        );
+
+        let instructions = TransactionInstructions::new(&meta, &transaction, InstructionDecoding::Strict).unwrap();
+        let called: Vec<(Instruction, u32, Option<usize>)> = instructions
+            .for_program(program)
+            .map(|instruction| {
+                (
+                    instruction.to_instruction().unwrap(),
+                    instruction.stack_height,
+                    instruction.parent_index,
+                )
+            })
+            .collect();
+        assert_eq!(called, vec![
+            (
+                Instruction::new_with_bytes(program, &[4], vec![
+                    AccountMeta::new(account, false),
+                    AccountMeta::new(payer, true)
+                ]),
+                3,
+                Some(1)
+            ),
+            (
+                Instruction::new_with_bytes(program, &[2], vec![AccountMeta::new(account, false)]),
+                1,
+                None
+            ),
+        ]);
+    }
+
+    #[test]
+    fn strict_rejects_unknown_account_index() {
+        let [payer, program] = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let (meta, transaction) = legacy_transaction(&[payer, program], 1, vec![compiled(1, &[0], &[1])], vec![inner(
+            0,
+            vec![(compiled(1, &[200], &[2]), Some(2))],
+        )]);
+
+        let err = extract_instructions(&meta, &transaction, InstructionDecoding::Strict).unwrap_err();
+        assert!(matches!(err, Error::MissingKey { index: 200, .. }), "{err:?}");
+
+        let instructions = extract_instructions(&meta, &transaction, InstructionDecoding::Lenient).unwrap();
+        assert_eq!(instructions[1].instruction.accounts[0].pubkey, Pubkey::default());
+    }
+
+    #[test]
+    fn strict_rejects_loaded_addresses_not_matching_lookups() {
+        // two writable and one readonly address are selected, the readonly one did not load
+        let message = v0::Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 1,
+            },
+            account_keys: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            recent_blockhash: Hash::new_unique(),
+            instructions: vec![compiled(1, &[0, 2, 3], &[1])],
+            address_table_lookups: vec![MessageAddressTableLookup {
+                account_key: Pubkey::new_unique(),
+                writable_indexes: vec![1, 2],
+                readonly_indexes: vec![3],
+            }],
+        };
+        let meta = TransactionStatusMeta {
+            loaded_addresses: LoadedAddresses {
+                writable: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+                readonly: vec![],
+            },
+            ..TransactionStatusMeta::default()
+        };
+        let transaction = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::V0(message),
+        };
+
+        let err = TransactionInstructions::new(&meta, &transaction, InstructionDecoding::Strict).err().unwrap();
+        assert!(
+            matches!(err, Error::LookupTableLoad {
+                kind: "readonly",
+                expected: 1,
+                loaded: 0
+            }),
+            "{err:?}"
+        );
+        assert!(TransactionInstructions::new(&meta, &transaction, InstructionDecoding::Lenient).is_ok());
+    }
+
+    #[test]
+    fn strict_rejects_malformed_messages() {
+        let [payer, program] = [Pubkey::new_unique(), Pubkey::new_unique()];
+        // inner instructions of a third instruction in a message of two
+        let (meta, transaction) = legacy_transaction(
+            &[payer, program],
+            1,
+            vec![compiled(1, &[0], &[1]), compiled(1, &[0], &[2])],
+            vec![inner(2, vec![(compiled(1, &[0], &[3]), Some(2))])],
+        );
+        let err = TransactionInstructions::new(&meta, &transaction, InstructionDecoding::Strict).err().unwrap();
+        assert!(matches!(err, Error::MalformedMessage(_)), "{err:?}");
+
+        // program id out of range of the account keys
+        let (meta, transaction) = legacy_transaction(&[payer, program], 1, vec![compiled(200, &[0], &[1])], vec![]);
+        let err = TransactionInstructions::new(&meta, &transaction, InstructionDecoding::Strict).err().unwrap();
+        assert!(matches!(err, Error::MalformedMessage(_)), "{err:?}");
+    }
 }
@@ -0,0 +1,119 @@
+use async_trait::async_trait;
+use solana_sdk::account::Account;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+use solana_transaction_status::TransactionStatusMeta;
+use std::sync::Arc;
+use yellowstone_grpc_proto::geyser::SlotStatus;
+
+#[derive(Debug, Clone)]
+pub struct TransactionUpdate {
+    pub signature: Signature,
+    pub transaction: VersionedTransaction,
+    pub meta: TransactionStatusMeta,
+    pub is_vote: bool,
+    pub slot: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct AccountUpdate {
+    pub pubkey: Pubkey,
+    pub account: Account,
+    pub slot: u64,
+    // part of the snapshot sent right after subscribing rather than a live write
+    pub is_startup: bool,
+    // transaction that wrote the account, not set for startup updates
+    pub txn_signature: Option<Signature>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SlotUpdate {
+    pub slot: u64,
+    pub parent: Option<u64>,
+    pub status: SlotStatus,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockMetaUpdate {
+    pub slot: u64,
+    pub blockhash: Hash,
+    pub block_height: Option<u64>,
+    // receiver of the block's fee reward
+    pub leader: Option<Pubkey>,
+}
+
+/// Receives what the Geyser subscription delivers. Every callback ignores its update unless overridden, so a handler
+/// only implements what it subscribed to.
+#[async_trait]
+pub trait GeyserHandler: Send + Sync {
+    async fn transaction(&self, _transaction: TransactionUpdate) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn account(&self, _account: AccountUpdate) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn slot(&self, _slot: SlotUpdate) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn block_meta(&self, _block_meta: BlockMetaUpdate) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum HandlerUpdate {
+    Transaction(Box<TransactionUpdate>),
+    Account(AccountUpdate),
+    Slot(SlotUpdate),
+    BlockMeta(BlockMetaUpdate),
+}
+
+impl HandlerUpdate {
+    pub async fn dispatch(self, handler: &dyn GeyserHandler) -> anyhow::Result<()> {
+        match self {
+            HandlerUpdate::Transaction(transaction) => handler.transaction(*transaction).await,
+            HandlerUpdate::Account(account) => handler.account(account).await,
+            HandlerUpdate::Slot(slot) => handler.slot(slot).await,
+            HandlerUpdate::BlockMeta(block_meta) => handler.block_meta(block_meta).await,
+        }
+    }
+}
+
+/// Feeds every update to several handlers in turn, a failing handler does not keep the others from running.
+pub struct Handlers(pub Vec<Arc<dyn GeyserHandler>>);
+
+impl Handlers {
+    async fn dispatch(&self, update: HandlerUpdate) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for handler in &self.0 {
+            if let Err(e) = update.clone().dispatch(handler.as_ref()).await {
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl GeyserHandler for Handlers {
+    async fn transaction(&self, transaction: TransactionUpdate) -> anyhow::Result<()> {
+        self.dispatch(HandlerUpdate::Transaction(Box::new(transaction))).await
+    }
+
+    async fn account(&self, account: AccountUpdate) -> anyhow::Result<()> {
+        self.dispatch(HandlerUpdate::Account(account)).await
+    }
+
+    async fn slot(&self, slot: SlotUpdate) -> anyhow::Result<()> {
+        self.dispatch(HandlerUpdate::Slot(slot)).await
+    }
+
+    async fn block_meta(&self, block_meta: BlockMetaUpdate) -> anyhow::Result<()> {
+        self.dispatch(HandlerUpdate::BlockMeta(block_meta)).await
+    }
+}
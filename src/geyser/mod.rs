@@ -1,80 +1,146 @@
-use crate::pumpfun::PumpFunController;
+use crate::config::{GeyserEndpointConfig, GeyserKeepaliveConfig, GeyserReconnectConfig};
+use crate::geyser::handler::GeyserHandler;
+use crate::geyser::tracked_accounts::TrackedAccounts;
 use async_trait::async_trait;
-use futures::StreamExt;
-use solana_sdk::{pubkey::Pubkey, signature::Signature};
-use std::collections::HashSet;
-use std::convert::TryFrom;
-use std::time::Duration;
+use futures::{SinkExt, StreamExt};
+use rand::Rng;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, sync::Arc};
 use thiserror::Error;
-use tokio::sync::RwLock;
-use tracing::error;
-use yellowstone_grpc_client::GeyserGrpcClient;
-use yellowstone_grpc_proto::tonic::transport::ClientTlsConfig;
-use yellowstone_grpc_proto::{
-    convert_from::{create_tx_meta, create_tx_versioned},
-    geyser::{
-        CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts, SubscribeRequestFilterTransactions,
-        subscribe_update::UpdateOneof,
-    },
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info, warn};
+use yellowstone_grpc_client::{GeyserGrpcClient, Interceptor};
+use yellowstone_grpc_proto::geyser::{
+    CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts, SubscribeRequestFilterBlocksMeta,
+    SubscribeRequestFilterSlots, SubscribeRequestFilterTransactions, SubscribeRequestPing, SubscribeUpdate,
+    SubscribeUpdateAccount, subscribe_update::UpdateOneof,
 };
+use yellowstone_grpc_proto::tonic::Code;
+use yellowstone_grpc_proto::tonic::transport::ClientTlsConfig;
+
+pub mod handler;
+pub mod pipeline;
+pub mod race;
+pub mod recording;
+pub mod tracked_accounts;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    // waiting to reconnect after `attempt` consecutive failures
+    Reconnecting { attempt: u32 },
+    // gave up after `max_retries`
+    Failed,
+}
+
+/// Bounded map of recently seen keys (signatures, account writes, ...) to when they were first received, the oldest
+/// are forgotten first.
+#[derive(Debug)]
+pub struct Dedup<K> {
+    seen: HashMap<K, Instant>,
+    order: VecDeque<K>,
+    capacity: usize,
+}
+
+impl<K: Hash + Eq + Copy> Dedup<K> {
+    pub fn new(capacity: usize) -> Self {
+        Dedup {
+            seen: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns when the key was first received if it was already seen.
+    pub fn insert(&mut self, key: K, received_at: Instant) -> Option<Instant> {
+        if let Some(first_received_at) = self.seen.get(&key) {
+            return Some(*first_received_at);
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(key, received_at);
+        self.order.push_back(key);
+        None
+    }
+}
+
+/// Update as received from one endpoint.
+#[derive(Debug)]
+pub struct GeyserUpdate {
+    pub endpoint: String,
+    pub received_at: Instant,
+    pub update: SubscribeUpdate,
+}
+
+/// What every endpoint is subscribed to, on top of the tracked accounts.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionFilters {
+    pub accounts: HashMap<String, SubscribeRequestFilterAccounts>,
+    pub transactions: HashMap<String, SubscribeRequestFilterTransactions>,
+    pub slots: HashMap<String, SubscribeRequestFilterSlots>,
+    pub blocks_meta: HashMap<String, SubscribeRequestFilterBlocksMeta>,
+}
 
 #[derive(Debug)]
 pub struct YellowstoneGrpcGeyserClient {
+    pub name: String,
     pub endpoint: String,
     pub x_token: Option<String>,
     pub commitment: Option<CommitmentLevel>,
-    pub account_filters: HashMap<String, SubscribeRequestFilterAccounts>,
-    pub transaction_filters: HashMap<String, SubscribeRequestFilterTransactions>,
-    pub account_deletions_tracked: Arc<RwLock<HashSet<Pubkey>>>,
+    pub filters: SubscriptionFilters,
+    pub tracked_accounts: Arc<TrackedAccounts>,
+    pub reconnect: GeyserReconnectConfig,
+    pub keepalive: GeyserKeepaliveConfig,
+    connection_state: watch::Sender<ConnectionState>,
+    // last slot a transaction was processed in, 0 before the first one
+    last_slot: AtomicU64,
 }
 
 impl YellowstoneGrpcGeyserClient {
     pub fn new(
-        endpoint: String,
-        x_token: Option<String>,
+        name: String,
+        endpoint: GeyserEndpointConfig,
         commitment: Option<CommitmentLevel>,
-        account_filters: HashMap<String, SubscribeRequestFilterAccounts>,
-        transaction_filters: HashMap<String, SubscribeRequestFilterTransactions>,
-        account_deletions_tracked: Arc<RwLock<HashSet<Pubkey>>>,
+        filters: SubscriptionFilters,
+        tracked_accounts: Arc<TrackedAccounts>,
+        reconnect: GeyserReconnectConfig,
     ) -> Self {
         YellowstoneGrpcGeyserClient {
-            endpoint,
-            x_token,
+            name,
+            endpoint: endpoint.url,
+            x_token: endpoint.x_token,
             commitment,
-            account_filters,
-            transaction_filters,
-            account_deletions_tracked,
+            filters,
+            tracked_accounts,
+            reconnect,
+            keepalive: GeyserKeepaliveConfig::default(),
+            connection_state: watch::Sender::new(ConnectionState::Connecting),
+            last_slot: AtomicU64::new(0),
         }
     }
-}
-
-pub type GeyserResult<T> = Result<T, Error>;
 
-#[async_trait]
-pub trait YellowstoneGrpcGeyser: Send + Sync {
-    async fn consume(&self, pump_fun_controller: PumpFunController) -> GeyserResult<()>;
-}
+    pub fn with_keepalive(mut self, keepalive: GeyserKeepaliveConfig) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
 
-#[derive(Error, Debug)]
-pub enum Error {
-    #[error("Custom error: {0}")]
-    Custom(String),
-}
+    /// Lets the rest of the app follow the feed, e.g. to stop trading while it is down.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.connection_state.subscribe()
+    }
 
-#[async_trait]
-impl YellowstoneGrpcGeyser for YellowstoneGrpcGeyserClient {
-    async fn consume(&self, mut pump_fun_controller: PumpFunController) -> GeyserResult<()> {
-        let endpoint = self.endpoint.clone();
-        let x_token = self.x_token.clone();
-        let commitment = self.commitment;
-        let account_filters = self.account_filters.clone();
-        let transaction_filters = self.transaction_filters.clone();
-        let _account_deletions_tracked = self.account_deletions_tracked.clone();
-
-        let mut geyser_client = GeyserGrpcClient::build_from_shared(endpoint)
+    async fn connect(&self) -> GeyserResult<GeyserGrpcClient<impl Interceptor>> {
+        GeyserGrpcClient::build_from_shared(self.endpoint.clone())
             .map_err(|err| Error::Custom(err.to_string()))?
-            .x_token(x_token)
+            .x_token(self.x_token.clone())
             .map_err(|err| Error::Custom(err.to_string()))?
             .connect_timeout(Duration::from_secs(15))
             .timeout(Duration::from_secs(15))
@@ -82,89 +148,223 @@ impl YellowstoneGrpcGeyser for YellowstoneGrpcGeyserClient {
             .map_err(|err| Error::Custom(err.to_string()))?
             .connect()
             .await
+            .map_err(|err| Error::Custom(err.to_string()))
+    }
+
+    /// Slot to resume from so nothing that happened while disconnected is lost, the slot itself is replayed
+    /// since it may have been processed only partially.
+    fn resume_slot(&self) -> Option<u64> {
+        match self.last_slot.load(Ordering::Relaxed) {
+            0 => None,
+            slot => Some(slot),
+        }
+    }
+
+    fn subscribe_request(&self, from_slot: Option<u64>) -> SubscribeRequest {
+        let mut accounts = self.filters.accounts.clone();
+        let tracked_accounts = self.tracked_accounts.pubkeys();
+        // a filter without accounts or owners would match every account
+        if !tracked_accounts.is_empty() {
+            accounts.insert("tracked_accounts".to_string(), SubscribeRequestFilterAccounts {
+                account: tracked_accounts,
+                ..Default::default()
+            });
+        }
+        SubscribeRequest {
+            slots: self.filters.slots.clone(),
+            accounts,
+            transactions: self.filters.transactions.clone(),
+            transactions_status: HashMap::new(),
+            entry: HashMap::new(),
+            blocks: HashMap::new(),
+            blocks_meta: self.filters.blocks_meta.clone(),
+            commitment: self.commitment.map(|x| x as i32),
+            accounts_data_slice: vec![],
+            ping: None,
+            from_slot,
+        }
+    }
+
+    // a request carrying only a ping is answered with a pong and leaves the subscription filters untouched
+    fn ping_request(id: i32) -> SubscribeRequest {
+        SubscribeRequest {
+            ping: Some(SubscribeRequestPing { id }),
+            ..Default::default()
+        }
+    }
+
+    /// Connects and streams until the stream breaks or stops answering pings. Returns whether any update was
+    /// received, so a stream that worked for a while does not count towards `max_retries`.
+    async fn subscribe_and_stream(&self, updates: &mpsc::Sender<GeyserUpdate>) -> GeyserResult<bool> {
+        // a fresh client per attempt, the old channel may be wedged after a transport error
+        let mut geyser_client = self.connect().await?;
+        let from_slot = self.resume_slot();
+        let mut tracked_accounts_changed = self.tracked_accounts.subscribe();
+        let (mut subscribe_tx, mut stream) = geyser_client
+            .subscribe_with_request(Some(self.subscribe_request(from_slot)))
+            .await
             .map_err(|err| Error::Custom(err.to_string()))?;
 
-        let _ = tokio::spawn(async move {
-            let subscribe_request = SubscribeRequest {
-                slots: HashMap::new(),
-                accounts: account_filters,
-                transactions: transaction_filters,
-                transactions_status: HashMap::new(),
-                entry: HashMap::new(),
-                blocks: HashMap::new(),
-                blocks_meta: HashMap::new(),
-                commitment: commitment.map(|x| x as i32),
-                accounts_data_slice: vec![],
-                ping: None,
-                from_slot: None,
-            };
-
-            loop {
-                match geyser_client.subscribe_with_request(Some(subscribe_request.clone())).await {
-                    Ok((_subscribe_tx, mut stream)) => {
-                        while let Some(message) = stream.next().await {
-                            match message {
-                                Ok(msg) => match msg.update_oneof {
-                                    Some(UpdateOneof::Transaction(transaction_update)) => {
-                                        let _start_time = std::time::Instant::now();
-
-                                        if let Some(transaction_info) = transaction_update.transaction {
-                                            let Ok(signature) = Signature::try_from(transaction_info.signature) else {
-                                                continue;
-                                            };
-                                            let Some(yellowstone_transaction) = transaction_info.transaction else {
-                                                continue;
-                                            };
-                                            let Some(yellowstone_tx_meta) = transaction_info.meta else {
-                                                continue;
-                                            };
-                                            let Ok(versioned_transaction) =
-                                                create_tx_versioned(yellowstone_transaction)
-                                            else {
-                                                continue;
-                                            };
-                                            let meta_original = match create_tx_meta(yellowstone_tx_meta) {
-                                                Ok(meta) => meta,
-                                                Err(err) => {
-                                                    error!("Failed to create transaction meta: {:?}", err);
-                                                    continue;
-                                                }
-                                            };
-                                            // info!("signature {:?}", signature);
-                                            let _ = pump_fun_controller
-                                                .transaction_handler(
-                                                    signature,
-                                                    versioned_transaction,
-                                                    meta_original,
-                                                    transaction_info.is_vote,
-                                                    transaction_update.slot,
-                                                )
-                                                .await;
-                                        } else {
-                                            error!(
-                                                "No transaction info in `UpdateOneof::Transaction` at slot {}",
-                                                transaction_update.slot
-                                            );
+        info!("subscribed to geyser {} from slot {:?}", self.endpoint, from_slot);
+        self.connection_state.send_replace(ConnectionState::Connected);
+
+        let pong_timeout = Duration::from_millis(self.keepalive.pong_timeout_ms);
+        let mut ping_interval = tokio::time::interval(Duration::from_millis(self.keepalive.ping_interval_ms));
+        ping_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut ping_id: i32 = 0;
+        let mut last_pong = Instant::now();
+        let mut received = false;
+
+        loop {
+            tokio::select! {
+                message = stream.next() => {
+                    let Some(message) = message else {
+                        warn!("geyser stream {} closed", self.endpoint);
+                        return Ok(received);
+                    };
+                    let received_at = Instant::now();
+                    match message {
+                        Ok(msg) => {
+                            received = true;
+                            match msg.update_oneof {
+                                // load balancers in front of the server drop streams whose client never talks
+                                Some(UpdateOneof::Ping(_)) => {
+                                    if let Err(e) = subscribe_tx.send(Self::ping_request(ping_id)).await {
+                                        error!("cannot answer geyser {} ping: {:?}", self.endpoint, e);
+                                        return Ok(received);
+                                    }
+                                }
+                                Some(UpdateOneof::Pong(_)) => last_pong = Instant::now(),
+                                _ => {
+                                    match &msg.update_oneof {
+                                        Some(UpdateOneof::Transaction(transaction_update)) => {
+                                            self.last_slot.fetch_max(transaction_update.slot, Ordering::Relaxed);
+                                        }
+                                        Some(UpdateOneof::Account(account_update)) => {
+                                            self.untrack_deleted_account(account_update);
                                         }
+                                        _ => {}
+                                    }
+                                    let update = GeyserUpdate {
+                                        endpoint: self.name.clone(),
+                                        received_at,
+                                        update: msg,
+                                    };
+                                    if updates.send(update).await.is_err() {
+                                        return Ok(received);
                                     }
-
-                                    _ => {}
-                                },
-                                Err(error) => {
-                                    error!("Geyser stream error: {error:?}");
-                                    break;
                                 }
                             }
                         }
+                        Err(error) => {
+                            error!("Geyser stream error: {error:?}");
+                            // the slot fell out of the server's replay window, resume live and accept the gap
+                            if error.code() == Code::InvalidArgument && from_slot.is_some() {
+                                warn!(
+                                    "geyser {} cannot resume from slot {:?}, resubscribing live",
+                                    self.endpoint, from_slot
+                                );
+                                self.last_slot.store(0, Ordering::Relaxed);
+                            }
+                            return Ok(received);
+                        }
+                    }
+                }
+                _ = tracked_accounts_changed.changed() => {
+                    // a new request replaces the previous filters entirely
+                    if let Err(e) = subscribe_tx.send(self.subscribe_request(None)).await {
+                        error!("cannot update geyser {} subscription: {:?}", self.endpoint, e);
+                        return Ok(received);
                     }
-                    Err(e) => {
-                        error!("Failed to subscribe: {:?}", e);
+                    info!("updated geyser {} tracked accounts", self.endpoint);
+                }
+                _ = ping_interval.tick() => {
+                    if last_pong.elapsed() > pong_timeout {
+                        warn!("no pong from geyser {} for {:?}, reconnecting", self.endpoint, last_pong.elapsed());
+                        return Ok(received);
+                    }
+                    ping_id = ping_id.wrapping_add(1);
+                    if let Err(e) = subscribe_tx.send(Self::ping_request(ping_id)).await {
+                        error!("cannot ping geyser {}: {:?}", self.endpoint, e);
+                        return Ok(received);
                     }
                 }
             }
-        })
-        .await;
+        }
+    }
+
+    // closed accounts are reported once with no lamports and never again
+    fn untrack_deleted_account(&self, account_update: &SubscribeUpdateAccount) {
+        let Some(account_info) = &account_update.account else {
+            return;
+        };
+        if account_info.lamports != 0 {
+            return;
+        }
+        if let Ok(pubkey) = Pubkey::try_from(account_info.pubkey.as_slice()) {
+            if self.tracked_accounts.contains(&pubkey) {
+                info!("tracked account {} was deleted", pubkey);
+                self.tracked_accounts.remove(&pubkey);
+            }
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .reconnect
+            .initial_backoff_ms
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.reconnect.max_backoff_ms);
+        // jitter so several clients don't hammer the endpoint in lockstep
+        Duration::from_millis(rand::thread_rng().gen_range(backoff / 2..=backoff))
+    }
+
+    /// Streams updates into `updates`, reconnecting until `max_retries` is exhausted or nobody is listening anymore.
+    pub async fn stream(&self, updates: mpsc::Sender<GeyserUpdate>) -> GeyserResult<()> {
+        let mut attempt: u32 = 0;
+
+        loop {
+            match self.subscribe_and_stream(&updates).await {
+                Ok(true) => attempt = 0,
+                Ok(false) => {}
+                Err(e) => error!("Failed to subscribe to geyser {}: {:?}", self.name, e),
+            }
+            if updates.is_closed() {
+                return Ok(());
+            }
+            attempt += 1;
+
+            if self.reconnect.max_retries.is_some_and(|max_retries| attempt > max_retries) {
+                self.connection_state.send_replace(ConnectionState::Failed);
+                return Err(Error::Custom(format!(
+                    "giving up on geyser {} after {} attempts",
+                    self.endpoint, attempt
+                )));
+            }
 
-        Ok(())
+            self.connection_state.send_replace(ConnectionState::Reconnecting { attempt });
+            let backoff = self.backoff(attempt);
+            warn!(
+                "reconnecting to geyser {} in {:?} (attempt {})",
+                self.endpoint, backoff, attempt
+            );
+            tokio::time::sleep(backoff).await;
+        }
     }
 }
+
+pub type GeyserResult<T> = Result<T, Error>;
+
+#[async_trait]
+pub trait YellowstoneGrpcGeyser: Send + Sync {
+    async fn consume(&self, handler: Arc<dyn GeyserHandler>) -> GeyserResult<()>;
+
+    /// Whether the feed is live, handlers should not trade on it while it is not.
+    fn connection_state(&self) -> watch::Receiver<ConnectionState>;
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Custom error: {0}")]
+    Custom(String),
+}
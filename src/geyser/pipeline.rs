@@ -0,0 +1,133 @@
+use crate::config::{HandlerPipelineConfig, OverflowPolicy};
+use crate::geyser::handler::{GeyserHandler, HandlerUpdate};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy)]
+pub struct QueueStats {
+    pub depth: usize,
+    // deepest the queue got since the previous stats
+    pub max_depth: usize,
+    // updates discarded by `DropOldest` since the start
+    pub dropped: u64,
+}
+
+struct UpdateQueue {
+    updates: Mutex<VecDeque<HandlerUpdate>>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    // wakes workers when an update is queued
+    queued: Notify,
+    // wakes a blocked `push` when a worker takes an update
+    taken: Notify,
+    max_depth: AtomicUsize,
+    dropped: AtomicU64,
+    // updates taken by a worker and not handled yet
+    in_flight: AtomicUsize,
+}
+
+impl UpdateQueue {
+    async fn push(&self, update: HandlerUpdate) {
+        loop {
+            let taken = self.taken.notified();
+            {
+                let mut updates = self.updates.lock().unwrap();
+                if updates.len() >= self.capacity && self.overflow == OverflowPolicy::DropOldest {
+                    updates.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                if updates.len() < self.capacity {
+                    updates.push_back(update);
+                    self.max_depth.fetch_max(updates.len(), Ordering::Relaxed);
+                    self.queued.notify_one();
+                    return;
+                }
+            }
+            taken.await;
+        }
+    }
+
+    async fn pop(&self) -> HandlerUpdate {
+        loop {
+            let queued = self.queued.notified();
+            if let Some(update) = self.updates.lock().unwrap().pop_front() {
+                self.in_flight.fetch_add(1, Ordering::Relaxed);
+                self.taken.notify_one();
+                return update;
+            }
+            queued.await;
+        }
+    }
+}
+
+/// Bounded queue between the Geyser streams and the handler, drained by a pool of workers so a slow handler does
+/// not hold up reading the streams.
+pub struct HandlerPipeline {
+    queue: Arc<UpdateQueue>,
+}
+
+impl HandlerPipeline {
+    pub fn spawn(config: &HandlerPipelineConfig, handler: Arc<dyn GeyserHandler>) -> Self {
+        let queue = Arc::new(UpdateQueue {
+            updates: Mutex::new(VecDeque::with_capacity(config.queue_capacity)),
+            capacity: config.queue_capacity.max(1),
+            overflow: config.overflow,
+            queued: Notify::new(),
+            taken: Notify::new(),
+            max_depth: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+        });
+
+        for _ in 0..config.workers.max(1) {
+            let queue = queue.clone();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                loop {
+                    let update = queue.pop().await;
+                    if let Err(e) = update.dispatch(handler.as_ref()).await {
+                        error!("geyser handler failed: {:?}", e);
+                    }
+                    queue.in_flight.fetch_sub(1, Ordering::Relaxed);
+                }
+            });
+        }
+
+        HandlerPipeline { queue }
+    }
+
+    /// Queues an update, waiting for room or dropping the oldest one when full depending on the overflow policy.
+    pub async fn push(&self, update: HandlerUpdate) {
+        self.queue.push(update).await;
+    }
+
+    /// Waits until every queued update has been handled.
+    pub async fn wait_idle(&self) {
+        while !self.queue.updates.lock().unwrap().is_empty() || self.queue.in_flight.load(Ordering::Relaxed) > 0 {
+            tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let depth = self.queue.updates.lock().unwrap().len();
+        QueueStats {
+            depth,
+            max_depth: self.queue.max_depth.swap(depth, Ordering::Relaxed),
+            dropped: self.queue.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn log_stats(&self) {
+        let stats = self.stats();
+        info!(
+            "handler queue depth {} (max {}), dropped {}",
+            stats.depth, stats.max_depth, stats.dropped
+        );
+    }
+}
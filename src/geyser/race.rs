@@ -0,0 +1,369 @@
+use crate::config::HandlerPipelineConfig;
+use crate::geyser::handler::{
+    AccountUpdate, BlockMetaUpdate, GeyserHandler, HandlerUpdate, SlotUpdate, TransactionUpdate,
+};
+use crate::geyser::pipeline::HandlerPipeline;
+use crate::geyser::recording::Recorder;
+use crate::geyser::{
+    ConnectionState, Dedup, Error, GeyserResult, GeyserUpdate, YellowstoneGrpcGeyser, YellowstoneGrpcGeyserClient,
+};
+use async_trait::async_trait;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info};
+use yellowstone_grpc_proto::convert_from::{create_account, create_tx_meta, create_tx_versioned};
+use yellowstone_grpc_proto::geyser::subscribe_update::UpdateOneof;
+use yellowstone_grpc_proto::geyser::{
+    SlotStatus, SubscribeUpdateAccount, SubscribeUpdateBlockMeta, SubscribeUpdateSlot, SubscribeUpdateTransaction,
+};
+use yellowstone_grpc_proto::prelude::RewardType;
+
+// how many recent updates are remembered to drop the copies from slower endpoints and replays after a resume
+const SEEN_CAPACITY: usize = 100_000;
+const UPDATES_BUFFER: usize = 10_000;
+const STATS_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default, Clone)]
+pub struct EndpointStats {
+    // transactions received, including the ones another endpoint delivered first
+    pub received: u64,
+    pub wins: u64,
+    // how much later than the fastest endpoint copies arrived, 0 for wins
+    pub total_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl EndpointStats {
+    fn record(&mut self, delay: Duration) {
+        self.received += 1;
+        if delay.is_zero() {
+            self.wins += 1;
+        }
+        self.total_delay += delay;
+        self.max_delay = self.max_delay.max(delay);
+    }
+
+    pub fn avg_delay(&self) -> Duration {
+        match self.received {
+            0 => Duration::ZERO,
+            received => self.total_delay / received as u32,
+        }
+    }
+}
+
+// an account write is identified by the transaction that made it, startup updates have none
+type AccountWrite = (Pubkey, u64, Option<Signature>);
+
+struct RaceState {
+    seen_signatures: Dedup<Signature>,
+    seen_account_writes: Dedup<AccountWrite>,
+    seen_slots: Dedup<(u64, i32)>,
+    seen_blocks: Dedup<u64>,
+    stats: BTreeMap<String, EndpointStats>,
+}
+
+/// Subscribes to every endpoint at once and forwards the first copy of each transaction, so we trade on whichever
+/// provider is fastest at the moment.
+pub struct GeyserRace {
+    clients: Vec<Arc<YellowstoneGrpcGeyserClient>>,
+    pipeline: HandlerPipelineConfig,
+    recorder: Option<Recorder>,
+    connection_state: watch::Sender<ConnectionState>,
+}
+
+impl GeyserRace {
+    pub fn new(clients: Vec<YellowstoneGrpcGeyserClient>, pipeline: HandlerPipelineConfig) -> Self {
+        GeyserRace {
+            clients: clients.into_iter().map(Arc::new).collect(),
+            pipeline,
+            recorder: None,
+            connection_state: watch::Sender::new(ConnectionState::Connecting),
+        }
+    }
+
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    async fn follow_connection_states(
+        mut receivers: Vec<watch::Receiver<ConnectionState>>,
+        connection_state: watch::Sender<ConnectionState>,
+    ) {
+        loop {
+            let states = receivers.iter_mut().map(|receiver| *receiver.borrow_and_update()).collect::<Vec<_>>();
+            connection_state.send_replace(combine_connection_states(&states));
+
+            let changes = receivers.iter_mut().map(|receiver| Box::pin(receiver.changed()));
+            if futures::future::select_all(changes).await.0.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Decodes the first copy of an update, copies from slower endpoints come back as None.
+    fn decode(update: GeyserUpdate, state: &mut RaceState) -> anyhow::Result<Option<HandlerUpdate>> {
+        match update.update.update_oneof {
+            Some(UpdateOneof::Transaction(transaction_update)) => {
+                Self::decode_transaction(transaction_update, update.endpoint, update.received_at, state)
+            }
+            Some(UpdateOneof::Account(account_update)) => {
+                Self::decode_account(account_update, update.received_at, state)
+            }
+            Some(UpdateOneof::Slot(slot_update)) => Self::decode_slot(slot_update, update.received_at, state),
+            Some(UpdateOneof::BlockMeta(block_meta_update)) => {
+                Self::decode_block_meta(block_meta_update, update.received_at, state)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn decode_transaction(
+        transaction_update: SubscribeUpdateTransaction,
+        endpoint: String,
+        received_at: Instant,
+        state: &mut RaceState,
+    ) -> anyhow::Result<Option<HandlerUpdate>> {
+        let Some(transaction_info) = transaction_update.transaction else {
+            error!(
+                "No transaction info in `UpdateOneof::Transaction` at slot {}",
+                transaction_update.slot
+            );
+            return Ok(None);
+        };
+        let Ok(signature) = Signature::try_from(transaction_info.signature) else {
+            return Ok(None);
+        };
+
+        let first_received_at = state.seen_signatures.insert(signature, received_at);
+        let delay = first_received_at
+            .map(|first_received_at| received_at.saturating_duration_since(first_received_at))
+            .unwrap_or_default();
+        state.stats.entry(endpoint).or_default().record(delay);
+        if first_received_at.is_some() {
+            return Ok(None);
+        }
+
+        let Some(yellowstone_transaction) = transaction_info.transaction else {
+            return Ok(None);
+        };
+        let Some(yellowstone_tx_meta) = transaction_info.meta else {
+            return Ok(None);
+        };
+        let Ok(versioned_transaction) = create_tx_versioned(yellowstone_transaction) else {
+            return Ok(None);
+        };
+        let meta_original = match create_tx_meta(yellowstone_tx_meta) {
+            Ok(meta) => meta,
+            Err(err) => {
+                error!("Failed to create transaction meta: {:?}", err);
+                return Ok(None);
+            }
+        };
+        Ok(Some(HandlerUpdate::Transaction(Box::new(TransactionUpdate {
+            signature,
+            transaction: versioned_transaction,
+            meta: meta_original,
+            is_vote: transaction_info.is_vote,
+            slot: transaction_update.slot,
+        }))))
+    }
+
+    fn decode_account(
+        account_update: SubscribeUpdateAccount,
+        received_at: Instant,
+        state: &mut RaceState,
+    ) -> anyhow::Result<Option<HandlerUpdate>> {
+        let Some(account_info) = account_update.account else {
+            return Ok(None);
+        };
+        let txn_signature = match &account_info.txn_signature {
+            Some(txn_signature) => Some(Signature::try_from(txn_signature.as_slice())?),
+            None => None,
+        };
+        let (pubkey, account) = create_account(account_info).map_err(|err| anyhow::anyhow!(err))?;
+        let write = (pubkey, account_update.slot, txn_signature);
+        if state.seen_account_writes.insert(write, received_at).is_some() {
+            return Ok(None);
+        }
+
+        Ok(Some(HandlerUpdate::Account(AccountUpdate {
+            pubkey,
+            account,
+            slot: account_update.slot,
+            is_startup: account_update.is_startup,
+            txn_signature,
+        })))
+    }
+
+    fn decode_slot(
+        slot_update: SubscribeUpdateSlot,
+        received_at: Instant,
+        state: &mut RaceState,
+    ) -> anyhow::Result<Option<HandlerUpdate>> {
+        if state.seen_slots.insert((slot_update.slot, slot_update.status), received_at).is_some() {
+            return Ok(None);
+        }
+        let status = SlotStatus::try_from(slot_update.status)?;
+
+        Ok(Some(HandlerUpdate::Slot(SlotUpdate {
+            slot: slot_update.slot,
+            parent: slot_update.parent,
+            status,
+        })))
+    }
+
+    fn decode_block_meta(
+        block_meta_update: SubscribeUpdateBlockMeta,
+        received_at: Instant,
+        state: &mut RaceState,
+    ) -> anyhow::Result<Option<HandlerUpdate>> {
+        if state.seen_blocks.insert(block_meta_update.slot, received_at).is_some() {
+            return Ok(None);
+        }
+        let leader = block_meta_update
+            .rewards
+            .iter()
+            .flat_map(|rewards| &rewards.rewards)
+            .find(|reward| reward.reward_type == RewardType::Fee as i32)
+            .map(|reward| Pubkey::from_str(&reward.pubkey))
+            .transpose()?;
+
+        Ok(Some(HandlerUpdate::BlockMeta(BlockMetaUpdate {
+            slot: block_meta_update.slot,
+            blockhash: Hash::from_str(&block_meta_update.blockhash)?,
+            block_height: block_meta_update.block_height.map(|block_height| block_height.block_height),
+            leader,
+        })))
+    }
+}
+
+fn combine_connection_states(states: &[ConnectionState]) -> ConnectionState {
+    if states.contains(&ConnectionState::Connected) {
+        return ConnectionState::Connected;
+    }
+    if states.contains(&ConnectionState::Connecting) {
+        return ConnectionState::Connecting;
+    }
+    states
+        .iter()
+        .filter_map(|state| match state {
+            ConnectionState::Reconnecting { attempt } => Some(*attempt),
+            _ => None,
+        })
+        .min()
+        .map(|attempt| ConnectionState::Reconnecting { attempt })
+        .unwrap_or(ConnectionState::Failed)
+}
+
+fn log_stats(stats: &BTreeMap<String, EndpointStats>) {
+    let total = stats.values().map(|stats| stats.wins).sum::<u64>();
+    for (endpoint, stats) in stats {
+        info!(
+            "geyser {}: won {}/{} ({:.1}%), received {}, avg delay {:?}, max delay {:?}",
+            endpoint,
+            stats.wins,
+            total,
+            stats.wins as f64 * 100.0 / total.max(1) as f64,
+            stats.received,
+            stats.avg_delay(),
+            stats.max_delay
+        );
+    }
+}
+
+/// Decodes, deduplicates, records and queues updates for the handler until every sender is gone.
+pub async fn dispatch_updates(
+    mut receiver: mpsc::Receiver<GeyserUpdate>,
+    handler: Arc<dyn GeyserHandler>,
+    pipeline: &HandlerPipelineConfig,
+    recorder: Option<&Recorder>,
+) -> HandlerPipeline {
+    let mut state = RaceState {
+        seen_signatures: Dedup::new(SEEN_CAPACITY),
+        seen_account_writes: Dedup::new(SEEN_CAPACITY),
+        seen_slots: Dedup::new(SEEN_CAPACITY),
+        seen_blocks: Dedup::new(SEEN_CAPACITY),
+        stats: BTreeMap::new(),
+    };
+    let pipeline = HandlerPipeline::spawn(pipeline, handler);
+    let mut report_interval =
+        tokio::time::interval_at((Instant::now() + STATS_REPORT_INTERVAL).into(), STATS_REPORT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            update = receiver.recv() => {
+                let Some(update) = update else {
+                    break;
+                };
+                if let Some(recorder) = recorder {
+                    recorder.record(&update).await;
+                }
+                match GeyserRace::decode(update, &mut state) {
+                    Ok(Some(update)) => pipeline.push(update).await,
+                    Ok(None) => {}
+                    Err(e) => error!("cannot decode geyser update: {:?}", e),
+                }
+            }
+            _ = report_interval.tick() => {
+                log_stats(&state.stats);
+                pipeline.log_stats();
+            }
+        }
+    }
+
+    log_stats(&state.stats);
+    pipeline.log_stats();
+    if let Some(recorder) = recorder {
+        recorder.flush().await;
+    }
+    pipeline
+}
+
+#[async_trait]
+impl YellowstoneGrpcGeyser for GeyserRace {
+    async fn consume(&self, handler: Arc<dyn GeyserHandler>) -> GeyserResult<()> {
+        let (sender, receiver) = mpsc::channel::<GeyserUpdate>(UPDATES_BUFFER);
+        for client in &self.clients {
+            let client = client.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                if let Err(e) = client.stream(sender).await {
+                    error!("geyser {} stopped: {:?}", client.name, e);
+                }
+            });
+        }
+        // the channel closes once every endpoint gave up
+        drop(sender);
+
+        tokio::spawn(Self::follow_connection_states(
+            self.clients.iter().map(|client| client.connection_state()).collect(),
+            self.connection_state.clone(),
+        ));
+
+        tokio::select! {
+            _ = dispatch_updates(receiver, handler, &self.pipeline, self.recorder.as_ref()) => {
+                Err(Error::Custom("all geyser endpoints gave up".to_string()))
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("shutting down");
+                if let Some(recorder) = &self.recorder {
+                    recorder.flush().await;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Connected as long as any endpoint is, failed once all of them gave up.
+    fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.connection_state.subscribe()
+    }
+}
@@ -0,0 +1,214 @@
+use crate::config::HandlerPipelineConfig;
+use crate::geyser::handler::GeyserHandler;
+use crate::geyser::race::dispatch_updates;
+use crate::geyser::{ConnectionState, Error, GeyserResult, GeyserUpdate, YellowstoneGrpcGeyser};
+use async_trait::async_trait;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{error, info};
+use yellowstone_grpc_proto::geyser::SubscribeUpdate;
+use yellowstone_grpc_proto::prost::Message;
+
+const REPLAY_ENDPOINT: &str = "replay";
+const REPLAY_BUFFER: usize = 1_000;
+const RECORDING_BUFFER: usize = 10_000;
+// at most this much of the recording is lost when the process dies
+const RECORDING_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes every received update to a file as the receive time in microseconds since the epoch (u64 little endian)
+/// followed by the length-delimited `SubscribeUpdate` protobuf. The file is written by a separate task so the ingest
+/// loop never waits on the disk.
+pub struct Recorder {
+    sender: mpsc::Sender<RecorderEvent>,
+    // wall clock time of `started_at`, updates only carry an `Instant`
+    started: SystemTime,
+    started_at: Instant,
+}
+
+enum RecorderEvent {
+    Update(Vec<u8>),
+    Flush(oneshot::Sender<()>),
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> GeyserResult<Self> {
+        let file = File::create(path).map_err(|err| Error::Custom(format!("cannot create {}: {}", path, err)))?;
+        info!("recording geyser updates to {}", path);
+        let (sender, receiver) = mpsc::channel(RECORDING_BUFFER);
+        tokio::spawn(Self::run(receiver, BufWriter::new(tokio::fs::File::from_std(file))));
+        Ok(Recorder {
+            sender,
+            started: SystemTime::now(),
+            started_at: Instant::now(),
+        })
+    }
+
+    async fn run(mut receiver: mpsc::Receiver<RecorderEvent>, mut writer: BufWriter<tokio::fs::File>) {
+        let mut flush_interval = tokio::time::interval(RECORDING_FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                event = receiver.recv() => match event {
+                    Some(RecorderEvent::Update(record)) => {
+                        if let Err(e) = writer.write_all(&record).await {
+                            error!("cannot record geyser update: {:?}", e);
+                        }
+                    }
+                    Some(RecorderEvent::Flush(reply)) => {
+                        Self::flush_writer(&mut writer).await;
+                        let _ = reply.send(());
+                    }
+                    None => break,
+                },
+                _ = flush_interval.tick() => Self::flush_writer(&mut writer).await,
+            }
+        }
+        Self::flush_writer(&mut writer).await;
+    }
+
+    async fn flush_writer(writer: &mut BufWriter<tokio::fs::File>) {
+        if let Err(e) = writer.flush().await {
+            error!("cannot flush geyser recording: {:?}", e);
+        }
+    }
+
+    /// Queues the update for the writer, only waits if the writer has fallen `RECORDING_BUFFER` updates behind.
+    pub async fn record(&self, update: &GeyserUpdate) {
+        let received_at = self.started + update.received_at.saturating_duration_since(self.started_at);
+        let micros = received_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        let mut record = micros.to_le_bytes().to_vec();
+        record.extend(update.update.encode_length_delimited_to_vec());
+        if self.sender.send(RecorderEvent::Update(record)).await.is_err() {
+            error!("geyser recording writer is gone");
+        }
+    }
+
+    /// Returns once everything recorded so far is written to the file.
+    pub async fn flush(&self) {
+        let (reply, flushed) = oneshot::channel();
+        if self.sender.send(RecorderEvent::Flush(reply)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+struct RecordingReader {
+    reader: BufReader<File>,
+}
+
+impl RecordingReader {
+    // None at the end of the recording
+    fn next(&mut self) -> std::io::Result<Option<(u64, SubscribeUpdate)>> {
+        let mut micros = [0u8; 8];
+        match self.reader.read_exact(&mut micros) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let mut message = vec![0u8; self.read_length()?];
+        self.reader.read_exact(&mut message)?;
+        let update = SubscribeUpdate::decode(message.as_slice())
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))?;
+        Ok(Some((u64::from_le_bytes(micros), update)))
+    }
+
+    fn read_length(&mut self) -> std::io::Result<usize> {
+        let mut length = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0u8; 1];
+            self.reader.read_exact(&mut byte)?;
+            length |= ((byte[0] & 0x7f) as u64) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(length as usize);
+            }
+        }
+        Err(std::io::Error::new(ErrorKind::InvalidData, "length varint too long"))
+    }
+}
+
+/// Feeds a recording made by `Recorder` to a handler through the same dedup and pipeline as the live streams, at the
+/// recorded pace divided by `speed`, or as fast as possible when `speed` is 0.
+pub struct GeyserReplay {
+    path: String,
+    speed: f64,
+    pipeline: HandlerPipelineConfig,
+    connection_state: watch::Sender<ConnectionState>,
+}
+
+impl GeyserReplay {
+    pub fn new(path: String, speed: f64, pipeline: HandlerPipelineConfig) -> Self {
+        GeyserReplay {
+            path,
+            speed,
+            pipeline,
+            // handlers that only act on a connected feed should act on the replay too
+            connection_state: watch::Sender::new(ConnectionState::Connected),
+        }
+    }
+
+    async fn replay(
+        mut reader: RecordingReader,
+        speed: f64,
+        sender: mpsc::Sender<GeyserUpdate>,
+    ) -> std::io::Result<u64> {
+        let started_at = Instant::now();
+        let mut first_micros = None;
+        let mut replayed = 0;
+
+        while let Some((micros, update)) = reader.next()? {
+            if speed > 0.0 {
+                let first_micros = *first_micros.get_or_insert(micros);
+                let offset = Duration::from_micros(micros.saturating_sub(first_micros)).div_f64(speed);
+                tokio::time::sleep_until((started_at + offset).into()).await;
+            }
+            let update = GeyserUpdate {
+                endpoint: REPLAY_ENDPOINT.to_string(),
+                received_at: Instant::now(),
+                update,
+            };
+            if sender.send(update).await.is_err() {
+                break;
+            }
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+}
+
+#[async_trait]
+impl YellowstoneGrpcGeyser for GeyserReplay {
+    async fn consume(&self, handler: Arc<dyn GeyserHandler>) -> GeyserResult<()> {
+        let file =
+            File::open(&self.path).map_err(|err| Error::Custom(format!("cannot open {}: {}", self.path, err)))?;
+        let reader = RecordingReader {
+            reader: BufReader::new(file),
+        };
+        info!("replaying {} at {}x", self.path, self.speed);
+
+        let (sender, receiver) = mpsc::channel::<GeyserUpdate>(REPLAY_BUFFER);
+        let speed = self.speed;
+        let replay = tokio::spawn(Self::replay(reader, speed, sender));
+
+        let pipeline = dispatch_updates(receiver, handler, &self.pipeline, None).await;
+        pipeline.wait_idle().await;
+
+        match replay.await {
+            Ok(Ok(replayed)) => {
+                info!("replayed {} updates from {}", replayed, self.path);
+                Ok(())
+            }
+            Ok(Err(err)) => {
+                error!("cannot read {}: {:?}", self.path, err);
+                Err(Error::Custom(format!("cannot read {}: {}", self.path, err)))
+            }
+            Err(err) => Err(Error::Custom(err.to_string())),
+        }
+    }
+
+    fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.connection_state.subscribe()
+    }
+}
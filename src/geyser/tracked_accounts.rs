@@ -0,0 +1,52 @@
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::sync::RwLock;
+use tokio::sync::watch;
+
+/// Accounts subscribed to by pubkey. The set can change at runtime, every client resubscribes when it does and
+/// deleted accounts are dropped from it.
+#[derive(Debug)]
+pub struct TrackedAccounts {
+    pubkeys: RwLock<HashSet<Pubkey>>,
+    changed: watch::Sender<()>,
+}
+
+impl TrackedAccounts {
+    pub fn new() -> Self {
+        TrackedAccounts {
+            pubkeys: RwLock::new(HashSet::new()),
+            changed: watch::Sender::new(()),
+        }
+    }
+
+    pub fn insert(&self, pubkey: Pubkey) {
+        if self.pubkeys.write().unwrap().insert(pubkey) {
+            self.changed.send_replace(());
+        }
+    }
+
+    pub fn remove(&self, pubkey: &Pubkey) {
+        if self.pubkeys.write().unwrap().remove(pubkey) {
+            self.changed.send_replace(());
+        }
+    }
+
+    pub fn contains(&self, pubkey: &Pubkey) -> bool {
+        self.pubkeys.read().unwrap().contains(pubkey)
+    }
+
+    pub fn pubkeys(&self) -> Vec<String> {
+        self.pubkeys.read().unwrap().iter().map(|pubkey| pubkey.to_string()).collect()
+    }
+
+    /// Notified on every change after the receiver was created.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+}
+
+impl Default for TrackedAccounts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
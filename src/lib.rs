@@ -0,0 +1,10 @@
+pub mod bench;
+pub mod blockhash;
+pub mod clock;
+pub mod config;
+pub mod confirmation;
+pub mod core;
+pub mod geyser;
+pub mod metrics;
+pub mod pumpfun;
+pub mod tx_senders;
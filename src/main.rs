@@ -1,25 +1,24 @@
-use crate::bench::Bench;
-use crate::config::PingThingsArgs;
-use crate::geyser::{GeyserResult, YellowstoneGrpcGeyser, YellowstoneGrpcGeyserClient};
-use crate::pumpfun::PumpFunController;
-use solana_sdk::pubkey;
-use solana_sdk::pubkey::Pubkey;
-use std::collections::{HashMap, HashSet};
+use solana_sdk::commitment_config::CommitmentLevel as SolanaCommitmentLevel;
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use tracing::info;
+use week_edu_starter::bench::Bench;
+use week_edu_starter::clock::SlotClock;
+use week_edu_starter::config::{BondingCurveSubscription, PingThingsArgs};
+use week_edu_starter::geyser::handler::Handlers;
+use week_edu_starter::geyser::race::GeyserRace;
+use week_edu_starter::geyser::recording::{GeyserReplay, Recorder};
+use week_edu_starter::geyser::tracked_accounts::TrackedAccounts;
+use week_edu_starter::geyser::{
+    Error, GeyserResult, SubscriptionFilters, YellowstoneGrpcGeyser, YellowstoneGrpcGeyserClient,
+};
+use week_edu_starter::pumpfun::PumpFunController;
+use week_edu_starter::pumpfun::bonding_curve::BondingCurve;
 use yellowstone_grpc_proto::geyser::{
-    CommitmentLevel, SubscribeRequestFilterAccounts, SubscribeRequestFilterTransactions,
+    CommitmentLevel, SubscribeRequestFilterAccounts, SubscribeRequestFilterBlocksMeta, SubscribeRequestFilterSlots,
+    SubscribeRequestFilterTransactions,
 };
 
-pub const PUMPFUN_PROGRAM_ID: Pubkey = pubkey!("6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P");
-mod bench;
-mod config;
-mod core;
-mod geyser;
-mod pumpfun;
-mod tx_senders;
-
 #[tokio::main]
 pub async fn main() -> GeyserResult<()> {
     tracing::subscriber::set_global_default(
@@ -29,41 +28,108 @@ pub async fn main() -> GeyserResult<()> {
     )
     .unwrap();
 
-    let config_controller: PingThingsArgs = PingThingsArgs::new();
-    let bench_controller: Bench = Bench::new(config_controller.clone());
-
-    let pumpfun_controller: PumpFunController =
-        PumpFunController::new(config_controller.clone(), bench_controller.clone());
+    let config_controller: PingThingsArgs = PingThingsArgs::load().map_err(|e| Error::Custom(format!("{:?}", e)))?;
+    let slot_clock = Arc::new(SlotClock::new());
+    let bench_controller: Bench =
+        Bench::new(config_controller.clone(), slot_clock.clone()).map_err(|e| Error::Custom(format!("{:?}", e)))?;
 
     info!("starting with config {:?}", config_controller);
 
     env_logger::init();
     dotenv::dotenv().ok();
 
-    let account_filters: HashMap<String, SubscribeRequestFilterAccounts> = HashMap::new();
+    let mut account_filters: HashMap<String, SubscribeRequestFilterAccounts> = HashMap::new();
+    if config_controller.bonding_curve_accounts.subscribe == BondingCurveSubscription::Owner {
+        account_filters.insert(
+            "pumpfun_bonding_curve_filter".to_string(),
+            BondingCurve::owner_filter(config_controller.bonding_curve_accounts.data_size),
+        );
+    }
 
+    let subscription = &config_controller.subscription;
+    // None lets both kinds through, Some(false) only the ones that are not
     let transaction_filter = SubscribeRequestFilterTransactions {
-        vote: Some(false),
-        failed: Some(false),
-        account_include: vec![PUMPFUN_PROGRAM_ID.to_string().clone()],
-        account_exclude: vec![],
-        account_required: vec![],
+        vote: (!subscription.include_votes).then_some(false),
+        failed: (!subscription.include_failed).then_some(false),
+        account_include: subscription.account_include.clone(),
+        account_exclude: subscription.account_exclude.clone(),
+        account_required: subscription.account_required.clone(),
         signature: None,
     };
 
     let mut transaction_filters: HashMap<String, SubscribeRequestFilterTransactions> = HashMap::new();
 
-    transaction_filters.insert("pumpfun_transaction_filter".to_string(), transaction_filter);
+    transaction_filters.insert("transaction_filter".to_string(), transaction_filter);
+
+    let commitment = match subscription.commitment {
+        SolanaCommitmentLevel::Processed => CommitmentLevel::Processed,
+        SolanaCommitmentLevel::Confirmed => CommitmentLevel::Confirmed,
+        SolanaCommitmentLevel::Finalized => CommitmentLevel::Finalized,
+    };
+
+    let mut slot_filters: HashMap<String, SubscribeRequestFilterSlots> = HashMap::new();
+    slot_filters.insert("slot_clock_filter".to_string(), SubscribeRequestFilterSlots {
+        filter_by_commitment: Some(false),
+        interslot_updates: Some(config_controller.slot_clock.interslot_updates),
+    });
+    let mut blocks_meta_filters: HashMap<String, SubscribeRequestFilterBlocksMeta> = HashMap::new();
+    if config_controller.slot_clock.blocks_meta {
+        blocks_meta_filters.insert("slot_clock_filter".to_string(), SubscribeRequestFilterBlocksMeta {});
+    }
+
+    let subscription_filters = SubscriptionFilters {
+        accounts: account_filters,
+        transactions: transaction_filters,
+        slots: slot_filters,
+        blocks_meta: blocks_meta_filters,
+    };
+
+    let tracked_accounts = Arc::new(TrackedAccounts::new());
+    let recording = &config_controller.recording;
+    let yellowstone_grpc: Box<dyn YellowstoneGrpcGeyser> = match &recording.replay {
+        Some(replay) => Box::new(GeyserReplay::new(
+            replay.clone(),
+            recording.replay_speed,
+            config_controller.handler_pipeline.clone(),
+        )),
+        None => {
+            let geyser_endpoints = config_controller.geyser_endpoints();
+            if geyser_endpoints.is_empty() {
+                return Err(Error::Custom("no geyser endpoint configured".to_string()));
+            }
+            let geyser_clients = geyser_endpoints
+                .into_iter()
+                .map(|(name, endpoint)| {
+                    YellowstoneGrpcGeyserClient::new(
+                        name,
+                        endpoint,
+                        Some(commitment),
+                        subscription_filters.clone(),
+                        tracked_accounts.clone(),
+                        config_controller.geyser_reconnect.clone(),
+                    )
+                    .with_keepalive(config_controller.geyser_keepalive.clone())
+                })
+                .collect();
+            let mut race = GeyserRace::new(geyser_clients, config_controller.handler_pipeline.clone());
+            if let Some(record) = &recording.record {
+                race = race.with_recorder(Recorder::create(record)?);
+            }
+            Box::new(race)
+        }
+    };
 
-    let yellowstone_grpc = YellowstoneGrpcGeyserClient::new(
-        config_controller.geyser_url,
-        Some(config_controller.geyser_x_token),
-        Some(CommitmentLevel::Processed),
-        account_filters,
-        transaction_filters,
-        Arc::new(RwLock::new(HashSet::new())),
-    );
+    let pumpfun_controller = Arc::new(PumpFunController::new(
+        config_controller.clone(),
+        bench_controller.clone(),
+        yellowstone_grpc.connection_state(),
+        tracked_accounts,
+    ));
 
-    let _ = yellowstone_grpc.consume(pumpfun_controller).await;
-    Ok(())
+    let handlers = Handlers(vec![
+        slot_clock,
+        bench_controller.blockhash_service(),
+        pumpfun_controller,
+    ]);
+    yellowstone_grpc.consume(Arc::new(handlers)).await
 }
@@ -0,0 +1,107 @@
+use crate::tx_senders::solana_rpc::TxMetrics;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info};
+
+pub enum MetricsEvent {
+    Tx(TxMetrics),
+    Report(oneshot::Sender<BenchReport>),
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RpcSummary {
+    pub sent: u64,
+    pub landed: u64,
+    pub success: u64,
+    pub avg_elapsed: Option<u64>, // in milliseconds
+    pub avg_slot_latency: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct BenchReport {
+    pub results: Vec<TxMetrics>,
+    // rpc_name -> summary
+    pub summary: BTreeMap<String, RpcSummary>,
+}
+
+impl BenchReport {
+    fn new(results: Vec<TxMetrics>) -> Self {
+        let mut summary = BTreeMap::<String, RpcSummary>::new();
+        let mut elapsed = BTreeMap::<String, Vec<u64>>::new();
+        let mut slot_latency = BTreeMap::<String, Vec<u64>>::new();
+
+        for metrics in &results {
+            let rpc_summary = summary.entry(metrics.rpc_name.clone()).or_default();
+            rpc_summary.sent += 1;
+            if metrics.slot_landed.is_some() {
+                rpc_summary.landed += 1;
+            }
+            if metrics.success {
+                rpc_summary.success += 1;
+            }
+            if let Some(ms) = metrics.elapsed {
+                elapsed.entry(metrics.rpc_name.clone()).or_default().push(ms);
+            }
+            if let Some(slots) = metrics.slot_latency {
+                slot_latency.entry(metrics.rpc_name.clone()).or_default().push(slots);
+            }
+        }
+
+        for (rpc_name, rpc_summary) in summary.iter_mut() {
+            rpc_summary.avg_elapsed =
+                elapsed.get(rpc_name).map(|values| values.iter().sum::<u64>() / values.len() as u64);
+            rpc_summary.avg_slot_latency =
+                slot_latency.get(rpc_name).map(|values| values.iter().sum::<u64>() as f64 / values.len() as f64);
+        }
+
+        BenchReport { results, summary }
+    }
+}
+
+/// Handle to the background task that collects a `TxMetrics` for every send.
+#[derive(Clone)]
+pub struct MetricsCollector {
+    sender: mpsc::Sender<MetricsEvent>,
+}
+
+impl MetricsCollector {
+    pub fn spawn(buffer: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(buffer);
+        tokio::spawn(Self::run(receiver));
+        MetricsCollector { sender }
+    }
+
+    async fn run(mut receiver: mpsc::Receiver<MetricsEvent>) {
+        let mut results = Vec::<TxMetrics>::new();
+
+        while let Some(event) = receiver.recv().await {
+            match event {
+                MetricsEvent::Tx(mut metrics) => {
+                    metrics.slot_latency = metrics.slot_landed.map(|landed| landed.saturating_sub(metrics.slot_sent));
+                    info!("tx metrics: {:?}", metrics);
+                    results.push(metrics);
+                }
+                MetricsEvent::Report(reply) => {
+                    let _ = reply.send(BenchReport::new(results.clone()));
+                }
+            }
+        }
+    }
+
+    pub async fn record(&self, metrics: TxMetrics) {
+        if let Err(e) = self.sender.send(MetricsEvent::Tx(metrics)).await {
+            error!("metrics collector is gone: {:?}", e);
+        }
+    }
+
+    /// Returns everything recorded so far. Metrics recorded before this call are always included.
+    pub async fn report(&self) -> anyhow::Result<BenchReport> {
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(MetricsEvent::Report(reply))
+            .await
+            .map_err(|_| anyhow::anyhow!("metrics collector is gone"))?;
+        Ok(response.await?)
+    }
+}
@@ -0,0 +1,145 @@
+use crate::tx_senders::constants::PUMP_FUN_PROGRAM_ADDR;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::RwLock;
+use yellowstone_grpc_proto::geyser::subscribe_request_filter_accounts_filter::Filter;
+use yellowstone_grpc_proto::geyser::subscribe_request_filter_accounts_filter_memcmp::Data;
+use yellowstone_grpc_proto::geyser::{
+    SubscribeRequestFilterAccounts, SubscribeRequestFilterAccountsFilter, SubscribeRequestFilterAccountsFilterMemcmp,
+};
+
+pub const BONDING_CURVE_DISC: [u8; 8] = [23, 183, 248, 55, 96, 216, 172, 96];
+pub const PUMP_FUN_TOKEN_DECIMALS: u32 = 6;
+pub const BONDING_CURVE_SEED: &[u8] = b"bonding-curve";
+
+// newer bonding curves have more fields after `complete`, they are not needed here
+#[derive(Debug, BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct BondingCurve {
+    pub virtual_token_reserves: u64,
+    pub virtual_sol_reserves: u64,
+    pub real_token_reserves: u64,
+    pub real_sol_reserves: u64,
+    pub token_total_supply: u64,
+    // migrated, the curve does not trade anymore
+    pub complete: bool,
+}
+
+impl BondingCurve {
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        let Some((discriminator, mut data)) = data.split_first_chunk::<8>() else {
+            return Err(anyhow::anyhow!("bonding curve account too short"));
+        };
+        if *discriminator != BONDING_CURVE_DISC {
+            return Err(anyhow::anyhow!("not a bonding curve account"));
+        }
+        Ok(BorshDeserialize::deserialize(&mut data)?)
+    }
+
+    /// Bonding curve account of the pump.fun token `mint`.
+    pub fn find_address(mint: &Pubkey) -> Pubkey {
+        let program_id = Pubkey::from_str(PUMP_FUN_PROGRAM_ADDR).unwrap();
+        Pubkey::find_program_address(&[BONDING_CURVE_SEED, mint.as_ref()], &program_id).0
+    }
+
+    /// Price of one whole token in SOL.
+    pub fn price(&self) -> f64 {
+        if self.virtual_token_reserves == 0 {
+            return 0.0;
+        }
+        let sol = self.virtual_sol_reserves as f64 / LAMPORTS_PER_SOL as f64;
+        let tokens = self.virtual_token_reserves as f64 / 10u64.pow(PUMP_FUN_TOKEN_DECIMALS) as f64;
+        sol / tokens
+    }
+
+    /// Filter matching every bonding curve account of the pump.fun program.
+    pub fn owner_filter(data_size: Option<u64>) -> SubscribeRequestFilterAccounts {
+        let mut filters = vec![SubscribeRequestFilterAccountsFilter {
+            filter: Some(Filter::Memcmp(SubscribeRequestFilterAccountsFilterMemcmp {
+                offset: 0,
+                data: Some(Data::Bytes(BONDING_CURVE_DISC.to_vec())),
+            })),
+        }];
+        if let Some(data_size) = data_size {
+            filters.push(SubscribeRequestFilterAccountsFilter {
+                filter: Some(Filter::Datasize(data_size)),
+            });
+        }
+        SubscribeRequestFilterAccounts {
+            owner: vec![PUMP_FUN_PROGRAM_ADDR.to_string()],
+            filters,
+            ..Default::default()
+        }
+    }
+}
+
+/// Latest state of the bonding curves of tokens we bought. The handler pool applies updates out of order, so a write
+/// only lands if it is not older than the state it replaces.
+#[derive(Debug, Default)]
+pub struct HeldBondingCurves {
+    // bonding curve -> slot and state of the newest update, none until the first one after the buy
+    curves: RwLock<HashMap<Pubkey, Option<(u64, BondingCurve)>>>,
+}
+
+impl HeldBondingCurves {
+    pub fn hold(&self, bonding_curve: Pubkey) {
+        self.curves.write().unwrap().entry(bonding_curve).or_insert(None);
+    }
+
+    pub fn release(&self, bonding_curve: &Pubkey) {
+        self.curves.write().unwrap().remove(bonding_curve);
+    }
+
+    /// Stores `state` as seen at `slot`, false if the curve is not held or a newer state is already stored.
+    pub fn update(&self, bonding_curve: &Pubkey, slot: u64, state: BondingCurve) -> bool {
+        let mut curves = self.curves.write().unwrap();
+        let Some(held) = curves.get_mut(bonding_curve) else {
+            return false;
+        };
+        if matches!(held, Some((held_slot, _)) if *held_slot > slot) {
+            return false;
+        }
+        *held = Some((slot, state));
+        true
+    }
+
+    pub fn get(&self, bonding_curve: &Pubkey) -> Option<BondingCurve> {
+        self.curves.read().unwrap().get(bonding_curve).copied().flatten().map(|(_, state)| state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(real_sol_reserves: u64) -> BondingCurve {
+        BondingCurve {
+            virtual_token_reserves: 1_073_000_000_000_000,
+            virtual_sol_reserves: 30 * LAMPORTS_PER_SOL,
+            real_token_reserves: 793_100_000_000_000,
+            real_sol_reserves,
+            token_total_supply: 1_000_000_000_000_000,
+            complete: false,
+        }
+    }
+
+    #[test]
+    fn older_updates_do_not_replace_newer_state() {
+        let held = HeldBondingCurves::default();
+        let bonding_curve = Pubkey::new_unique();
+        assert!(!held.update(&bonding_curve, 10, curve(1)));
+        held.hold(bonding_curve);
+        assert!(held.get(&bonding_curve).is_none());
+
+        assert!(held.update(&bonding_curve, 12, curve(2)));
+        assert!(!held.update(&bonding_curve, 11, curve(3)));
+        assert_eq!(held.get(&bonding_curve).unwrap().real_sol_reserves, 2);
+        assert!(held.update(&bonding_curve, 12, curve(4)));
+        assert_eq!(held.get(&bonding_curve).unwrap().real_sol_reserves, 4);
+
+        held.release(&bonding_curve);
+        assert!(held.get(&bonding_curve).is_none());
+    }
+}
@@ -0,0 +1,288 @@
+use crate::core::TransactionInstructions;
+use crate::tx_senders::constants::PUMP_FUN_PROGRAM_ADDR;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_transaction_status::TransactionStatusMeta;
+use std::str::FromStr;
+
+pub const CREATE_EVENT_DISC: [u8; 8] = [27, 114, 169, 77, 222, 235, 99, 118];
+pub const TRADE_EVENT_DISC: [u8; 8] = [189, 219, 127, 211, 78, 230, 97, 238];
+pub const COMPLETE_EVENT_DISC: [u8; 8] = [95, 114, 97, 156, 212, 46, 152, 8];
+// prefix of the self CPI instructions anchor's `emit_cpi!` emits events with
+pub const EVENT_IX_TAG: [u8; 8] = [228, 69, 165, 46, 81, 203, 154, 29];
+
+const PROGRAM_DATA_LOG: &str = "Program data: ";
+
+// newer program versions append fields to the events, they are not needed here
+#[derive(Debug, BorshDeserialize, BorshSerialize, Clone)]
+pub struct CreateEvent {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub user: Pubkey,
+}
+
+#[derive(Debug, BorshDeserialize, BorshSerialize, Clone)]
+pub struct TradeEvent {
+    pub mint: Pubkey,
+    pub sol_amount: u64,
+    pub token_amount: u64,
+    pub is_buy: bool,
+    pub user: Pubkey,
+    pub timestamp: i64,
+    // reserves after the trade
+    pub virtual_sol_reserves: u64,
+    pub virtual_token_reserves: u64,
+}
+
+#[derive(Debug, BorshDeserialize, BorshSerialize, Clone)]
+pub struct CompleteEvent {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone)]
+pub enum PumpFunEvent {
+    Create(CreateEvent),
+    Trade(TradeEvent),
+    Complete(CompleteEvent),
+}
+
+impl PumpFunEvent {
+    /// Decodes a discriminator prefixed event, None for events that are not known here.
+    pub fn decode(data: &[u8]) -> anyhow::Result<Option<Self>> {
+        let Some((discriminator, mut data)) = data.split_first_chunk::<8>() else {
+            return Err(anyhow::anyhow!("event too short"));
+        };
+        let event = match *discriminator {
+            CREATE_EVENT_DISC => PumpFunEvent::Create(BorshDeserialize::deserialize(&mut data)?),
+            TRADE_EVENT_DISC => PumpFunEvent::Trade(BorshDeserialize::deserialize(&mut data)?),
+            COMPLETE_EVENT_DISC => PumpFunEvent::Complete(BorshDeserialize::deserialize(&mut data)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+
+    /// Events pump.fun emitted in a transaction, in order. They are read from its self CPI instructions, or from
+    /// the `Program data:` logs it wrote when it emitted none that way.
+    pub fn parse(meta: &TransactionStatusMeta, instructions: &TransactionInstructions) -> anyhow::Result<Vec<Self>> {
+        let program_id = Pubkey::from_str(PUMP_FUN_PROGRAM_ADDR)?;
+
+        let mut events = Vec::new();
+        for instruction in instructions.for_program(program_id) {
+            if instruction.stack_height == 1 {
+                continue;
+            }
+            if let Some(data) = instruction.data().strip_prefix(&EVENT_IX_TAG) {
+                events.extend(Self::decode(data)?);
+            }
+        }
+        if !events.is_empty() {
+            return Ok(events);
+        }
+
+        for data in program_data_logs(meta.log_messages.as_deref().unwrap_or_default(), PUMP_FUN_PROGRAM_ADDR) {
+            let data = STANDARD.decode(data)?;
+            events.extend(Self::decode(&data)?);
+        }
+        Ok(events)
+    }
+}
+
+// base64 payloads of the `Program data:` logs written while `program_id` was the executing program
+fn program_data_logs<'a>(logs: &'a [String], program_id: &'a str) -> impl Iterator<Item = &'a str> {
+    let mut invocations: Vec<&str> = Vec::new();
+    logs.iter().filter_map(move |log| {
+        if let Some(data) = log.strip_prefix(PROGRAM_DATA_LOG) {
+            return (invocations.last() == Some(&program_id)).then_some(data);
+        }
+        let mut words = log.strip_prefix("Program ")?.split_whitespace();
+        let (Some(program), Some(status)) = (words.next(), words.next()) else {
+            return None;
+        };
+        match status {
+            "invoke" => invocations.push(program),
+            "success" | "failed:" => {
+                invocations.pop();
+            }
+            _ => {}
+        }
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::InstructionDecoding;
+    use crate::pumpfun::instructions::{BUY_IX_DISC, CREATE_IX_DISC};
+    use solana_sdk::hash::Hash;
+    use solana_sdk::instruction::{AccountMeta, CompiledInstruction, Instruction};
+    use solana_sdk::message::{Message, VersionedMessage};
+    use solana_sdk::signature::Signature;
+    use solana_sdk::transaction::VersionedTransaction;
+    use solana_transaction_status::{InnerInstruction, InnerInstructions};
+
+    fn pump_fun_program() -> Pubkey {
+        Pubkey::from_str(PUMP_FUN_PROGRAM_ADDR).unwrap()
+    }
+
+    // pump.fun called from the message with `ix_data`, invoking itself with each of `self_cpi`
+    fn pump_fun_transaction(ix_data: &[u8], self_cpi: &[Vec<u8>]) -> (TransactionStatusMeta, VersionedTransaction) {
+        let user = Pubkey::new_unique();
+        let instruction = Instruction::new_with_bytes(pump_fun_program(), ix_data, vec![AccountMeta::new(user, true)]);
+        let message = Message::new_with_blockhash(&[instruction], Some(&user), &Hash::new_unique());
+        let program_id_index = message.instructions[0].program_id_index;
+
+        let meta = TransactionStatusMeta {
+            inner_instructions: Some(vec![InnerInstructions {
+                index: 0,
+                instructions: self_cpi
+                    .iter()
+                    .map(|data| InnerInstruction {
+                        instruction: CompiledInstruction::new_from_raw_parts(program_id_index, data.clone(), vec![]),
+                        stack_height: Some(2),
+                    })
+                    .collect(),
+            }]),
+            ..TransactionStatusMeta::default()
+        };
+        let transaction = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::Legacy(message),
+        };
+        (meta, transaction)
+    }
+
+    fn trade_event() -> TradeEvent {
+        TradeEvent {
+            mint: Pubkey::new_unique(),
+            sol_amount: 1_000_000,
+            token_amount: 35_000_000_000,
+            is_buy: true,
+            user: Pubkey::new_unique(),
+            timestamp: 1_700_000_000,
+            virtual_sol_reserves: 30_001_000_000,
+            virtual_token_reserves: 1_072_965_000_000_000,
+        }
+    }
+
+    fn create_event() -> CreateEvent {
+        CreateEvent {
+            name: "name".to_string(),
+            symbol: "SYM".to_string(),
+            uri: "https://example.com".to_string(),
+            mint: Pubkey::new_unique(),
+            bonding_curve: Pubkey::new_unique(),
+            user: Pubkey::new_unique(),
+        }
+    }
+
+    fn event_data(discriminator: [u8; 8], event: &impl BorshSerialize) -> Vec<u8> {
+        let mut data = discriminator.to_vec();
+        data.extend(borsh::to_vec(event).unwrap());
+        data
+    }
+
+    fn logs(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn events_from_self_cpi() {
+        let trade = trade_event();
+        let mut swap_data = BUY_IX_DISC.to_vec();
+        swap_data.extend([0; 16]);
+        let mut cpi_data = EVENT_IX_TAG.to_vec();
+        cpi_data.extend(event_data(TRADE_EVENT_DISC, &trade));
+        let (mut meta, transaction) = pump_fun_transaction(&swap_data, &[cpi_data]);
+        // ignored while the self CPI carries the events
+        let create = event_data(CREATE_EVENT_DISC, &create_event());
+        meta.log_messages = Some(logs(&[
+            &format!("Program {} invoke [1]", PUMP_FUN_PROGRAM_ADDR),
+            &format!("Program data: {}", STANDARD.encode(create)),
+            &format!("Program {} success", PUMP_FUN_PROGRAM_ADDR),
+        ]));
+
+        let instructions = TransactionInstructions::new(&meta, &transaction, InstructionDecoding::Strict).unwrap();
+        let events = PumpFunEvent::parse(&meta, &instructions).unwrap();
+        assert_eq!(events.len(), 1);
+        let PumpFunEvent::Trade(decoded) = &events[0] else {
+            panic!("{:?}", events[0]);
+        };
+        assert_eq!(decoded.mint, trade.mint);
+        assert_eq!(decoded.token_amount, trade.token_amount);
+        assert_eq!(decoded.virtual_token_reserves, trade.virtual_token_reserves);
+    }
+
+    #[test]
+    fn events_from_logs_without_self_cpi() {
+        let create = create_event();
+        let other = Pubkey::new_unique().to_string();
+        let (mut meta, transaction) = pump_fun_transaction(&CREATE_IX_DISC, &[]);
+        // the data logged by the program pump.fun invokes is not a pump.fun event
+        meta.log_messages = Some(logs(&[
+            &format!("Program {} invoke [1]", PUMP_FUN_PROGRAM_ADDR),
+            "Program log: Instruction: Create",
+            &format!("Program {} invoke [2]", other),
+            &format!(
+                "Program data: {}",
+                STANDARD.encode(event_data(TRADE_EVENT_DISC, &trade_event()))
+            ),
+            &format!("Program {} success", other),
+            &format!(
+                "Program data: {}",
+                STANDARD.encode(event_data(CREATE_EVENT_DISC, &create))
+            ),
+            &format!(
+                "Program {} consumed 50000 of 200000 compute units",
+                PUMP_FUN_PROGRAM_ADDR
+            ),
+            &format!("Program {} success", PUMP_FUN_PROGRAM_ADDR),
+        ]));
+
+        let instructions = TransactionInstructions::new(&meta, &transaction, InstructionDecoding::Strict).unwrap();
+        let events = PumpFunEvent::parse(&meta, &instructions).unwrap();
+        assert_eq!(events.len(), 1);
+        let PumpFunEvent::Create(decoded) = &events[0] else {
+            panic!("{:?}", events[0]);
+        };
+        assert_eq!(decoded.name, create.name);
+        assert_eq!(decoded.mint, create.mint);
+        assert_eq!(decoded.bonding_curve, create.bonding_curve);
+    }
+
+    #[test]
+    fn program_data_logs_follow_the_invocation_stack() {
+        let other = Pubkey::new_unique().to_string();
+        let logs = logs(&[
+            &format!("Program {} invoke [1]", PUMP_FUN_PROGRAM_ADDR),
+            "Program data: first",
+            &format!("Program {} invoke [2]", other),
+            "Program data: other",
+            &format!("Program {} failed: custom program error: 0x1", other),
+            "Program data: second",
+            &format!("Program {} invoke [2]", PUMP_FUN_PROGRAM_ADDR),
+            "Program data: third",
+            &format!("Program {} success", PUMP_FUN_PROGRAM_ADDR),
+            &format!("Program {} success", PUMP_FUN_PROGRAM_ADDR),
+            "Program data: after",
+        ]);
+
+        let data: Vec<&str> = program_data_logs(&logs, PUMP_FUN_PROGRAM_ADDR).collect();
+        assert_eq!(data, vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn unknown_events_are_skipped() {
+        assert!(PumpFunEvent::decode(&[0; 16]).unwrap().is_none());
+        assert!(PumpFunEvent::decode(&TRADE_EVENT_DISC).is_err());
+        assert!(PumpFunEvent::decode(&[0; 4]).is_err());
+    }
+}
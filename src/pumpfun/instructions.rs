@@ -0,0 +1,408 @@
+use crate::core::{CoreResult, InstructionRef};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_sdk::pubkey::Pubkey;
+
+pub const INITIALIZE_IX_DISC: [u8; 8] = [175, 175, 109, 31, 13, 152, 155, 237];
+pub const SET_PARAMS_IX_DISC: [u8; 8] = [27, 234, 178, 52, 147, 2, 187, 141];
+pub const CREATE_IX_DISC: [u8; 8] = [24, 30, 200, 40, 5, 28, 7, 119];
+pub const BUY_IX_DISC: [u8; 8] = [102, 6, 61, 18, 1, 218, 235, 234];
+pub const SELL_IX_DISC: [u8; 8] = [51, 230, 133, 164, 1, 127, 131, 173];
+pub const WITHDRAW_IX_DISC: [u8; 8] = [183, 18, 70, 156, 148, 109, 161, 34];
+pub const IX_DISCRIMINATOR_SIZE: usize = 8;
+
+#[derive(Debug, BorshDeserialize, BorshSerialize, Clone)]
+pub struct SetParamsIxData {
+    pub fee_recipient: Pubkey,
+    pub initial_virtual_token_reserves: u64,
+    pub initial_virtual_sol_reserves: u64,
+    pub initial_real_token_reserves: u64,
+    pub token_total_supply: u64,
+    pub fee_basis_points: u64,
+}
+
+#[derive(Debug, BorshDeserialize, BorshSerialize, Clone)]
+pub struct CreateIxData {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+}
+
+#[derive(Debug, BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct BuyIxData {
+    // tokens bought
+    pub amount: u64,
+    pub max_sol_cost: u64,
+}
+
+#[derive(Debug, BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct SellIxData {
+    // tokens sold
+    pub amount: u64,
+    pub min_sol_output: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InitializeAccounts {
+    pub global: Pubkey,
+    pub user: Pubkey,
+    pub system_program: Pubkey,
+}
+
+impl InitializeAccounts {
+    fn from_instruction(instruction: &InstructionRef) -> CoreResult<Self> {
+        Ok(InitializeAccounts {
+            global: instruction.account(0)?,
+            user: instruction.account(1)?,
+            system_program: instruction.account(2)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SetParamsAccounts {
+    pub global: Pubkey,
+    pub user: Pubkey,
+    pub system_program: Pubkey,
+    pub event_authority: Pubkey,
+    pub program: Pubkey,
+}
+
+impl SetParamsAccounts {
+    fn from_instruction(instruction: &InstructionRef) -> CoreResult<Self> {
+        Ok(SetParamsAccounts {
+            global: instruction.account(0)?,
+            user: instruction.account(1)?,
+            system_program: instruction.account(2)?,
+            event_authority: instruction.account(3)?,
+            program: instruction.account(4)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CreateAccounts {
+    pub mint: Pubkey,
+    pub mint_authority: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub associated_bonding_curve: Pubkey,
+    pub global: Pubkey,
+    pub mpl_token_metadata: Pubkey,
+    pub metadata: Pubkey,
+    pub user: Pubkey,
+    pub system_program: Pubkey,
+    pub token_program: Pubkey,
+    pub associated_token_program: Pubkey,
+    pub rent: Pubkey,
+    pub event_authority: Pubkey,
+    pub program: Pubkey,
+}
+
+impl CreateAccounts {
+    fn from_instruction(instruction: &InstructionRef) -> CoreResult<Self> {
+        Ok(CreateAccounts {
+            mint: instruction.account(0)?,
+            mint_authority: instruction.account(1)?,
+            bonding_curve: instruction.account(2)?,
+            associated_bonding_curve: instruction.account(3)?,
+            global: instruction.account(4)?,
+            mpl_token_metadata: instruction.account(5)?,
+            metadata: instruction.account(6)?,
+            user: instruction.account(7)?,
+            system_program: instruction.account(8)?,
+            token_program: instruction.account(9)?,
+            associated_token_program: instruction.account(10)?,
+            rent: instruction.account(11)?,
+            event_authority: instruction.account(12)?,
+            program: instruction.account(13)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BuyAccounts {
+    pub global: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub associated_bonding_curve: Pubkey,
+    pub associated_user: Pubkey,
+    pub user: Pubkey,
+    pub system_program: Pubkey,
+    pub token_program: Pubkey,
+    pub rent: Pubkey,
+    pub event_authority: Pubkey,
+    pub program: Pubkey,
+}
+
+impl BuyAccounts {
+    fn from_instruction(instruction: &InstructionRef) -> CoreResult<Self> {
+        Ok(BuyAccounts {
+            global: instruction.account(0)?,
+            fee_recipient: instruction.account(1)?,
+            mint: instruction.account(2)?,
+            bonding_curve: instruction.account(3)?,
+            associated_bonding_curve: instruction.account(4)?,
+            associated_user: instruction.account(5)?,
+            user: instruction.account(6)?,
+            system_program: instruction.account(7)?,
+            token_program: instruction.account(8)?,
+            rent: instruction.account(9)?,
+            event_authority: instruction.account(10)?,
+            program: instruction.account(11)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SellAccounts {
+    pub global: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub associated_bonding_curve: Pubkey,
+    pub associated_user: Pubkey,
+    pub user: Pubkey,
+    pub system_program: Pubkey,
+    pub associated_token_program: Pubkey,
+    pub token_program: Pubkey,
+    pub event_authority: Pubkey,
+    pub program: Pubkey,
+}
+
+impl SellAccounts {
+    fn from_instruction(instruction: &InstructionRef) -> CoreResult<Self> {
+        Ok(SellAccounts {
+            global: instruction.account(0)?,
+            fee_recipient: instruction.account(1)?,
+            mint: instruction.account(2)?,
+            bonding_curve: instruction.account(3)?,
+            associated_bonding_curve: instruction.account(4)?,
+            associated_user: instruction.account(5)?,
+            user: instruction.account(6)?,
+            system_program: instruction.account(7)?,
+            associated_token_program: instruction.account(8)?,
+            token_program: instruction.account(9)?,
+            event_authority: instruction.account(10)?,
+            program: instruction.account(11)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WithdrawAccounts {
+    pub global: Pubkey,
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub associated_bonding_curve: Pubkey,
+    pub associated_user: Pubkey,
+    pub user: Pubkey,
+    pub system_program: Pubkey,
+    pub token_program: Pubkey,
+    pub rent: Pubkey,
+    pub event_authority: Pubkey,
+    pub program: Pubkey,
+}
+
+impl WithdrawAccounts {
+    fn from_instruction(instruction: &InstructionRef) -> CoreResult<Self> {
+        Ok(WithdrawAccounts {
+            global: instruction.account(0)?,
+            mint: instruction.account(1)?,
+            bonding_curve: instruction.account(2)?,
+            associated_bonding_curve: instruction.account(3)?,
+            associated_user: instruction.account(4)?,
+            user: instruction.account(5)?,
+            system_program: instruction.account(6)?,
+            token_program: instruction.account(7)?,
+            rent: instruction.account(8)?,
+            event_authority: instruction.account(9)?,
+            program: instruction.account(10)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PumpFunInstruction {
+    Initialize {
+        accounts: InitializeAccounts,
+    },
+    SetParams {
+        data: SetParamsIxData,
+        accounts: SetParamsAccounts,
+    },
+    Create {
+        data: CreateIxData,
+        accounts: CreateAccounts,
+    },
+    Buy {
+        data: BuyIxData,
+        accounts: BuyAccounts,
+    },
+    Sell {
+        data: SellIxData,
+        accounts: SellAccounts,
+    },
+    Withdraw {
+        accounts: WithdrawAccounts,
+    },
+}
+
+impl PumpFunInstruction {
+    /// Decodes an instruction of the pump.fun program, None for instructions that are not known here like the self
+    /// CPI events.
+    pub fn decode(instruction: &InstructionRef) -> anyhow::Result<Option<Self>> {
+        let Some((discriminator, mut data)) = instruction.data().split_first_chunk::<IX_DISCRIMINATOR_SIZE>() else {
+            return Err(anyhow::anyhow!("pump.fun instruction too short"));
+        };
+        let decoded = match *discriminator {
+            INITIALIZE_IX_DISC => PumpFunInstruction::Initialize {
+                accounts: InitializeAccounts::from_instruction(instruction)?,
+            },
+            SET_PARAMS_IX_DISC => PumpFunInstruction::SetParams {
+                data: BorshDeserialize::deserialize(&mut data)?,
+                accounts: SetParamsAccounts::from_instruction(instruction)?,
+            },
+            CREATE_IX_DISC => PumpFunInstruction::Create {
+                data: BorshDeserialize::deserialize(&mut data)?,
+                accounts: CreateAccounts::from_instruction(instruction)?,
+            },
+            BUY_IX_DISC => PumpFunInstruction::Buy {
+                data: BorshDeserialize::deserialize(&mut data)?,
+                accounts: BuyAccounts::from_instruction(instruction)?,
+            },
+            SELL_IX_DISC => PumpFunInstruction::Sell {
+                data: BorshDeserialize::deserialize(&mut data)?,
+                accounts: SellAccounts::from_instruction(instruction)?,
+            },
+            WITHDRAW_IX_DISC => PumpFunInstruction::Withdraw {
+                accounts: WithdrawAccounts::from_instruction(instruction)?,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(decoded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::InstructionDecoding;
+    use crate::core::TransactionInstructions;
+    use crate::tx_senders::constants::PUMP_FUN_PROGRAM_ADDR;
+    use solana_sdk::instruction::{AccountMeta, Instruction};
+    use solana_sdk::message::{Message, VersionedMessage};
+    use solana_sdk::signature::Signature;
+    use solana_sdk::transaction::VersionedTransaction;
+    use solana_transaction_status::TransactionStatusMeta;
+    use std::str::FromStr;
+
+    // decodes `data` sent to pump.fun with `accounts` as the only instruction of a legacy transaction
+    fn decode(data: Vec<u8>, accounts: &[Pubkey]) -> anyhow::Result<Option<PumpFunInstruction>> {
+        let program = Pubkey::from_str(PUMP_FUN_PROGRAM_ADDR).unwrap();
+        let metas = accounts.iter().map(|account| AccountMeta::new(*account, false)).collect();
+        let payer = Pubkey::new_unique();
+        let message = Message::new(&[Instruction::new_with_bytes(program, &data, metas)], Some(&payer));
+        let transaction = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::Legacy(message),
+        };
+        let meta = TransactionStatusMeta::default();
+
+        let instructions = TransactionInstructions::new(&meta, &transaction, InstructionDecoding::Strict)?;
+        let instruction = instructions.for_program(program).next().unwrap();
+        PumpFunInstruction::decode(&instruction)
+    }
+
+    fn ix_data(discriminator: [u8; 8], data: &impl BorshSerialize) -> Vec<u8> {
+        let mut ix_data = discriminator.to_vec();
+        ix_data.extend(borsh::to_vec(data).unwrap());
+        ix_data
+    }
+
+    fn unique_accounts(count: usize) -> Vec<Pubkey> {
+        (0..count).map(|_| Pubkey::new_unique()).collect()
+    }
+
+    #[test]
+    fn decode_buy() {
+        let accounts = unique_accounts(12);
+        let data = BuyIxData {
+            amount: 35_000_000_000,
+            max_sol_cost: 1_010_000,
+        };
+
+        let Some(PumpFunInstruction::Buy { data, accounts: buy }) =
+            decode(ix_data(BUY_IX_DISC, &data), &accounts).unwrap()
+        else {
+            panic!("not a buy");
+        };
+        assert_eq!(data.amount, 35_000_000_000);
+        assert_eq!(data.max_sol_cost, 1_010_000);
+        assert_eq!(buy.mint, accounts[2]);
+        assert_eq!(buy.bonding_curve, accounts[3]);
+        assert_eq!(buy.associated_bonding_curve, accounts[4]);
+        assert_eq!(buy.user, accounts[6]);
+        assert_eq!(buy.program, accounts[11]);
+    }
+
+    #[test]
+    fn decode_sell() {
+        let accounts = unique_accounts(12);
+        let data = SellIxData {
+            amount: 35_000_000_000,
+            min_sol_output: 990_000,
+        };
+
+        let Some(PumpFunInstruction::Sell { data, accounts: sell }) =
+            decode(ix_data(SELL_IX_DISC, &data), &accounts).unwrap()
+        else {
+            panic!("not a sell");
+        };
+        assert_eq!(data.amount, 35_000_000_000);
+        assert_eq!(data.min_sol_output, 990_000);
+        assert_eq!(sell.mint, accounts[2]);
+        assert_eq!(sell.bonding_curve, accounts[3]);
+        assert_eq!(sell.user, accounts[6]);
+        assert_eq!(sell.associated_token_program, accounts[8]);
+        assert_eq!(sell.token_program, accounts[9]);
+    }
+
+    #[test]
+    fn decode_create() {
+        let accounts = unique_accounts(14);
+        let data = CreateIxData {
+            name: "name".to_string(),
+            symbol: "SYM".to_string(),
+            uri: "https://example.com".to_string(),
+        };
+
+        let Some(PumpFunInstruction::Create { data, accounts: create }) =
+            decode(ix_data(CREATE_IX_DISC, &data), &accounts).unwrap()
+        else {
+            panic!("not a create");
+        };
+        assert_eq!(data.name, "name");
+        assert_eq!(data.symbol, "SYM");
+        assert_eq!(data.uri, "https://example.com");
+        assert_eq!(create.mint, accounts[0]);
+        assert_eq!(create.bonding_curve, accounts[2]);
+        assert_eq!(create.associated_bonding_curve, accounts[3]);
+        assert_eq!(create.user, accounts[7]);
+        assert_eq!(create.program, accounts[13]);
+    }
+
+    #[test]
+    fn short_instructions_are_rejected() {
+        let accounts = unique_accounts(12);
+        assert!(decode(BUY_IX_DISC[..4].to_vec(), &accounts).is_err());
+        // discriminator without the data
+        assert!(decode(BUY_IX_DISC.to_vec(), &accounts).is_err());
+        // data without all the accounts
+        let data = ix_data(BUY_IX_DISC, &BuyIxData {
+            amount: 1,
+            max_sol_cost: 1,
+        });
+        assert!(decode(data, &accounts[..6]).is_err());
+        assert!(decode([0; 8].to_vec(), &accounts).unwrap().is_none());
+    }
+}
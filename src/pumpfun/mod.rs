@@ -1,79 +1,212 @@
 use crate::bench::Bench;
-use crate::config::PingThingsArgs;
-use crate::core::extract_instructions;
+use crate::config::{BondingCurveSubscription, PingThingsArgs};
+use crate::core::TransactionInstructions;
+use crate::geyser::ConnectionState;
+use crate::geyser::handler::{AccountUpdate, GeyserHandler, TransactionUpdate};
+use crate::geyser::tracked_accounts::TrackedAccounts;
+use crate::pumpfun::bonding_curve::{BondingCurve, HeldBondingCurves};
+use crate::pumpfun::events::PumpFunEvent;
+use crate::pumpfun::instructions::PumpFunInstruction;
 use crate::tx_senders::constants::PUMP_FUN_PROGRAM_ADDR;
-use borsh::{BorshDeserialize, BorshSerialize};
+use async_trait::async_trait;
 use solana_sdk::hash::Hash;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signature;
-use solana_sdk::transaction::VersionedTransaction;
-use solana_transaction_status::TransactionStatusMeta;
 use std::str::FromStr;
-use tracing::log::info;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::watch;
+use tracing::log::{debug, info, warn};
 
-pub const CREATE_IX_DISC: [u8; 8] = [24, 30, 200, 40, 5, 28, 7, 119];
-pub const IX_DISCRIMINATOR_SIZE: usize = 8;
-
-#[derive(Debug, BorshDeserialize, BorshSerialize, Clone)]
-pub struct CreateIxData {
-    pub name: String,
-    pub symbol: String,
-    pub uri: String,
-}
+pub mod bonding_curve;
+pub mod events;
+pub mod instructions;
 
 pub struct PumpFunController {
     config: PingThingsArgs,
     bench: Bench,
+    connection_state: watch::Receiver<ConnectionState>,
+    tracked_accounts: Arc<TrackedAccounts>,
+    held_curves: HeldBondingCurves,
 
-    is_buy: bool,
+    is_buy: AtomicBool,
 }
 
 impl PumpFunController {
-    pub fn new(config: PingThingsArgs, bench: Bench) -> Self {
+    pub fn new(
+        config: PingThingsArgs,
+        bench: Bench,
+        connection_state: watch::Receiver<ConnectionState>,
+        tracked_accounts: Arc<TrackedAccounts>,
+    ) -> Self {
         PumpFunController {
             config,
             bench,
-            is_buy: false,
+            connection_state,
+            tracked_accounts,
+            held_curves: HeldBondingCurves::default(),
+            is_buy: AtomicBool::new(false),
         }
     }
 
-    pub async fn transaction_handler(
-        &mut self,
-        _signature: Signature,
-        transaction: VersionedTransaction,
-        meta: TransactionStatusMeta,
-        _is_vote: bool,
-        _slot: u64,
-    ) -> anyhow::Result<()> {
-        let instructions: Vec<solana_sdk::instruction::Instruction> = extract_instructions(meta, transaction.clone())?;
+    /// Latest known state of a bonding curve we bought, none before its first account update.
+    pub fn held_bonding_curve(&self, bonding_curve: &Pubkey) -> Option<BondingCurve> {
+        self.held_curves.get(bonding_curve)
+    }
+
+    fn on_event(&self, signature: &Signature, slot: u64, event: &PumpFunEvent) {
+        match event {
+            PumpFunEvent::Create(create) => {
+                debug!(
+                    "create event in {} at slot {}: {} ({})",
+                    signature, slot, create.mint, create.symbol
+                );
+            }
+            PumpFunEvent::Trade(trade) => {
+                debug!(
+                    "{} of {} in {} at slot {}: {} lamports for {} tokens by {}",
+                    if trade.is_buy { "buy" } else { "sell" },
+                    trade.mint,
+                    signature,
+                    slot,
+                    trade.sol_amount,
+                    trade.token_amount,
+                    trade.user
+                );
+            }
+            PumpFunEvent::Complete(complete) => {
+                info!(
+                    "bonding curve {} of {} completed in {}",
+                    complete.bonding_curve, complete.mint, signature
+                );
+                // it does not trade anymore
+                self.tracked_accounts.remove(&complete.bonding_curve);
+                self.held_curves.release(&complete.bonding_curve);
+            }
+        }
+    }
+}
 
-        if !self.is_buy {
-            for instruction in instructions {
-                if instruction.program_id == Pubkey::from_str(PUMP_FUN_PROGRAM_ADDR)? {
-                    let ix_discriminator: [u8; 8] = instruction.data[0..IX_DISCRIMINATOR_SIZE].try_into()?;
+#[async_trait]
+impl GeyserHandler for PumpFunController {
+    async fn transaction(&self, update: TransactionUpdate) -> anyhow::Result<()> {
+        let TransactionUpdate {
+            signature,
+            transaction,
+            meta,
+            is_vote,
+            slot,
+        } = update;
+        if is_vote {
+            return Ok(());
+        }
+        let instructions = TransactionInstructions::new(&meta, &transaction, self.config.instruction_decoding)?;
 
-                    let mut ix_data = &instruction.data[IX_DISCRIMINATOR_SIZE..];
+        match PumpFunEvent::parse(&meta, &instructions) {
+            Ok(events) => events.iter().for_each(|event| self.on_event(&signature, slot, event)),
+            Err(e) => warn!("cannot parse pump.fun events in {}: {:?}", signature, e),
+        }
 
-                    let create_ix_data: CreateIxData = BorshDeserialize::deserialize(&mut ix_data)?;
+        // creates may come through a CPI from a router or launchpad program
+        for instruction in instructions.for_program(Pubkey::from_str(PUMP_FUN_PROGRAM_ADDR)?) {
+            let decoded = match PumpFunInstruction::decode(&instruction) {
+                Ok(Some(decoded)) => decoded,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("cannot decode pump.fun instruction in {}: {:?}", signature, e);
+                    continue;
+                }
+            };
 
-                    if ix_discriminator == CREATE_IX_DISC {
-                        info!("create ix: {:?}", create_ix_data);
+            match decoded {
+                PumpFunInstruction::Create { data, accounts } => {
+                    self.bench.observe_bonding_curve(accounts.bonding_curve);
 
-                        let token_address = instruction.accounts[0].pubkey;
-                        let bonding_curve = instruction.accounts[2].pubkey;
-                        let associated_bonding_curve = instruction.accounts[3].pubkey;
+                    if self.is_buy.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    // the feed may have dropped since this was queued, don't trade on stale data
+                    if *self.connection_state.borrow() != ConnectionState::Connected {
+                        warn!("geyser is not connected, skipping create ix");
+                        continue;
+                    }
+                    let invoked_by = instruction
+                        .parent_index
+                        .and_then(|parent_index| instructions.iter().nth(parent_index))
+                        .map(|parent| parent.program_id())
+                        .transpose()?;
+                    info!(
+                        "create ix in {} at slot {} (stack height {}, invoked by {:?}): {:?}",
+                        signature, slot, instruction.stack_height, invoked_by, data
+                    );
 
-                        let recent_blockhash: Hash = *transaction.message.recent_blockhash();
-                        self.is_buy = true;
-                        self.bench
-                            .clone()
-                            .send_buy_tx(recent_blockhash, token_address, bonding_curve, associated_bonding_curve)
-                            .await;
+                    let recent_blockhash: Hash = *transaction.message.recent_blockhash();
+                    // only the first create is bought
+                    if self.is_buy.swap(true, Ordering::Relaxed) {
+                        continue;
                     }
+                    self.held_curves.hold(accounts.bonding_curve);
+                    // subscribed before sending so the updates right after the buy lands are not missed
+                    if self.config.bonding_curve_accounts.subscribe == BondingCurveSubscription::Held {
+                        self.tracked_accounts.insert(accounts.bonding_curve);
+                    }
+                    self.bench
+                        .clone()
+                        .send_buy_tx(
+                            recent_blockhash,
+                            accounts.mint,
+                            accounts.bonding_curve,
+                            accounts.associated_bonding_curve,
+                        )
+                        .await;
+                }
+                PumpFunInstruction::Buy { data, accounts } => {
+                    debug!(
+                        "buy ix in {} at slot {}: {} tokens of {} for at most {} lamports by {}",
+                        signature, slot, data.amount, accounts.mint, data.max_sol_cost, accounts.user
+                    );
+                }
+                PumpFunInstruction::Sell { data, accounts } => {
+                    debug!(
+                        "sell ix in {} at slot {}: {} tokens of {} for at least {} lamports by {}",
+                        signature, slot, data.amount, accounts.mint, data.min_sol_output, accounts.user
+                    );
                 }
+                PumpFunInstruction::Initialize { .. }
+                | PumpFunInstruction::SetParams { .. }
+                | PumpFunInstruction::Withdraw { .. } => {}
             }
         }
 
         Ok(())
     }
+
+    async fn account(&self, update: AccountUpdate) -> anyhow::Result<()> {
+        if update.account.lamports == 0 {
+            self.held_curves.release(&update.pubkey);
+            return Ok(());
+        }
+        let bonding_curve = BondingCurve::decode(&update.account.data)?;
+
+        if self.held_curves.update(&update.pubkey, update.slot, bonding_curve) && !update.is_startup {
+            info!(
+                "bonding curve {} at slot {} ({:?}): price {:.10} SOL, real reserves {} SOL {} tokens, complete {}",
+                update.pubkey,
+                update.slot,
+                update.txn_signature,
+                bonding_curve.price(),
+                bonding_curve.real_sol_reserves,
+                bonding_curve.real_token_reserves,
+                bonding_curve.complete
+            );
+        } else {
+            debug!(
+                "bonding curve {} at slot {}: price {:.10} SOL",
+                update.pubkey,
+                update.slot,
+                bonding_curve.price()
+            );
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,133 @@
+use crate::config::ComputeUnitConfig;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::VersionedMessage;
+use solana_sdk::message::v0::Message;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::{error, info};
+
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+// program id and account count of every non compute budget instruction
+type InstructionLayout = Vec<(Pubkey, usize)>;
+
+/// Compute unit limit used by `build_transaction_with_config`. With `auto` the first transaction of every instruction
+/// layout is simulated and what it consumed plus a margin is used for that layout from then on. That first
+/// transaction still goes out with `compute_unit_limit`, so buys only get a sized limit once `warm_up_compute_units`
+/// simulated their layout at startup.
+pub struct ComputeUnitSizer {
+    compute_unit_limit: u32,
+    config: ComputeUnitConfig,
+    http_rpc: Arc<RpcClient>,
+    // None while the simulation is in flight
+    limits: RwLock<HashMap<InstructionLayout, Option<u32>>>,
+}
+
+impl ComputeUnitSizer {
+    pub fn new(compute_unit_limit: u32, config: ComputeUnitConfig, http_rpc: Arc<RpcClient>) -> Self {
+        ComputeUnitSizer {
+            compute_unit_limit,
+            config,
+            http_rpc,
+            limits: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn layout(instructions: &[Instruction]) -> InstructionLayout {
+        instructions.iter().map(|instruction| (instruction.program_id, instruction.accounts.len())).collect()
+    }
+
+    pub fn limit_for(&self, instructions: &[Instruction]) -> u32 {
+        if !self.config.auto {
+            return self.compute_unit_limit;
+        }
+        match self.limits.read().unwrap().get(&Self::layout(instructions)) {
+            Some(Some(limit)) => *limit,
+            _ => self.compute_unit_limit,
+        }
+    }
+
+    /// Simulates `instructions` with `compute_unit_price` in the background unless their layout was already sized.
+    pub fn size_in_background(
+        self: &Arc<Self>,
+        payer: &Pubkey,
+        instructions: &[Instruction],
+        compute_unit_price: u64,
+        recent_blockhash: Hash,
+    ) {
+        if !self.config.auto {
+            return;
+        }
+        let layout = Self::layout(instructions);
+        {
+            let mut limits = self.limits.write().unwrap();
+            if limits.contains_key(&layout) {
+                return;
+            }
+            limits.insert(layout.clone(), None);
+        }
+
+        // the price instruction costs compute units too
+        let mut simulated_instructions = vec![ComputeBudgetInstruction::set_compute_unit_limit(MAX_COMPUTE_UNIT_LIMIT)];
+        if compute_unit_price > 0 {
+            simulated_instructions.push(ComputeBudgetInstruction::set_compute_unit_price(compute_unit_price));
+        }
+        simulated_instructions.extend_from_slice(instructions);
+        let message = match Message::try_compile(payer, &simulated_instructions, &[], recent_blockhash) {
+            Ok(message) => VersionedMessage::V0(message),
+            Err(e) => {
+                error!("cannot compile transaction to simulate: {:?}", e);
+                self.limits.write().unwrap().remove(&layout);
+                return;
+            }
+        };
+        let transaction = VersionedTransaction {
+            signatures: vec![Signature::default(); message.header().num_required_signatures as usize],
+            message,
+        };
+
+        let sizer = self.clone();
+        tokio::spawn(async move {
+            match sizer.simulate(&transaction).await {
+                Ok(units_consumed) => {
+                    let limit = units_consumed
+                        .saturating_mul(100 + sizer.config.margin_percent as u64)
+                        .div_ceil(100)
+                        .min(MAX_COMPUTE_UNIT_LIMIT as u64) as u32;
+                    info!("simulated {} compute units, using a limit of {}", units_consumed, limit);
+                    sizer.limits.write().unwrap().insert(layout, Some(limit));
+                }
+                Err(e) => {
+                    error!("cannot size compute units: {:?}", e);
+                    // let the next transaction of this layout try again
+                    sizer.limits.write().unwrap().remove(&layout);
+                }
+            }
+        });
+    }
+
+    async fn simulate(&self, transaction: &VersionedTransaction) -> anyhow::Result<u64> {
+        let result = self
+            .http_rpc
+            .simulate_transaction_with_config(transaction, RpcSimulateTransactionConfig {
+                sig_verify: false,
+                replace_recent_blockhash: true,
+                commitment: Some(CommitmentConfig::processed()),
+                ..RpcSimulateTransactionConfig::default()
+            })
+            .await?
+            .value;
+        if let Some(err) = result.err {
+            return Err(anyhow::anyhow!("simulation failed: {} {:?}", err, result.logs));
+        }
+        result.units_consumed.ok_or(anyhow::anyhow!("simulation did not report units consumed"))
+    }
+}
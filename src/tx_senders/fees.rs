@@ -0,0 +1,106 @@
+use crate::config::{PriorityFeeConfig, PriorityFeeMode};
+use crate::tx_senders::constants::{PUMP_FUN_ACCOUNT_ADDR, PUMP_FUN_FEE_RECIPIENT_ADDR, PUMP_FUN_PROGRAM_ADDR};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{debug, error};
+
+// getRecentPrioritizationFees accepts at most 128 accounts
+const MAX_PRIORITIZATION_FEE_ACCOUNTS: usize = 128;
+
+/// Compute unit price used by `build_transaction_with_config`, either fixed or a percentile of recent
+/// prioritization fees paid around pump.fun, capped at `max`.
+pub struct PriorityFeeEstimator {
+    config: PriorityFeeConfig,
+    // in micro-lamports per compute unit
+    current: AtomicU64,
+    bonding_curves: RwLock<VecDeque<Pubkey>>,
+}
+
+impl PriorityFeeEstimator {
+    pub fn new(compute_unit_price: u64, config: PriorityFeeConfig) -> Self {
+        let estimator = PriorityFeeEstimator {
+            config,
+            current: AtomicU64::new(0),
+            bonding_curves: RwLock::new(VecDeque::new()),
+        };
+        estimator.set(compute_unit_price);
+        estimator
+    }
+
+    pub fn current(&self) -> u64 {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, compute_unit_price: u64) {
+        self.current.store(compute_unit_price.min(self.config.max), Ordering::Relaxed);
+    }
+
+    /// Adds a freshly created bonding curve to the accounts the estimate is scoped to, evicting the oldest.
+    pub fn observe_bonding_curve(&self, bonding_curve: Pubkey) {
+        if !matches!(self.config.mode, PriorityFeeMode::Estimate) || self.config.bonding_curves == 0 {
+            return;
+        }
+        let mut bonding_curves = self.bonding_curves.write().unwrap();
+        if bonding_curves.len() >= self.config.bonding_curves {
+            bonding_curves.pop_front();
+        }
+        bonding_curves.push_back(bonding_curve);
+    }
+
+    fn accounts(&self) -> Vec<Pubkey> {
+        let mut accounts = [
+            PUMP_FUN_PROGRAM_ADDR,
+            PUMP_FUN_ACCOUNT_ADDR,
+            PUMP_FUN_FEE_RECIPIENT_ADDR,
+        ]
+        .iter()
+        .map(|account| Pubkey::from_str(account).unwrap())
+        .collect::<Vec<Pubkey>>();
+        let bonding_curves = self.bonding_curves.read().unwrap();
+        accounts.extend(bonding_curves.iter().rev().take(MAX_PRIORITIZATION_FEE_ACCOUNTS - accounts.len()));
+        accounts
+    }
+
+    pub fn spawn_refresh(self: Arc<Self>, http_rpc: Arc<RpcClient>) {
+        if !matches!(self.config.mode, PriorityFeeMode::Estimate) {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(self.config.refresh_ms));
+            loop {
+                interval.tick().await;
+                match self.estimate(&http_rpc).await {
+                    Ok(Some(compute_unit_price)) => {
+                        self.set(compute_unit_price);
+                        debug!(
+                            "priority fee p{} is {}, using {}",
+                            self.config.percentile,
+                            compute_unit_price,
+                            self.current()
+                        );
+                    }
+                    Ok(None) => debug!("no recent prioritization fees, keeping {}", self.current()),
+                    Err(e) => error!("cannot refresh priority fee, keeping {}: {:?}", self.current(), e),
+                }
+            }
+        });
+    }
+
+    async fn estimate(&self, http_rpc: &RpcClient) -> anyhow::Result<Option<u64>> {
+        let fees = http_rpc.get_recent_prioritization_fees(&self.accounts()).await?;
+        let mut fees = fees.iter().map(|fee| fee.prioritization_fee).collect::<Vec<u64>>();
+        if fees.is_empty() {
+            return Ok(None);
+        }
+        fees.sort_unstable();
+        let percentile = self.config.percentile.min(100) as usize;
+        let index = ((fees.len() - 1) * percentile) / 100;
+        Ok(Some(fees[index]))
+    }
+}
@@ -1,5 +1,5 @@
-use crate::config::RpcType;
-use crate::tx_senders::transaction::{TransactionConfig, build_transaction_with_config};
+use crate::config::{BundleTx, JitoMode, RpcConfig, RpcType};
+use crate::tx_senders::transaction::{TransactionConfig, build_tip_transaction, build_transaction_with_config};
 use crate::tx_senders::{TxResult, TxSender};
 use anyhow::Context;
 use async_trait::async_trait;
@@ -9,23 +9,34 @@ use serde_json::{Value, json};
 use solana_sdk::bs58;
 use solana_sdk::hash::Hash;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
 use solana_sdk::transaction::VersionedTransaction;
+use std::str::FromStr;
 use tracing::debug;
 
+// the block engine accepts at most five transactions per bundle
+pub const MAX_BUNDLE_SIZE: usize = 5;
+
 pub struct JitoTxSender {
     url: String,
+    auth_header: Option<(String, String)>,
     name: String,
     client: Client,
     tx_config: TransactionConfig,
+    mode: JitoMode,
+    bundle: Vec<BundleTx>,
 }
 
 impl JitoTxSender {
-    pub fn new(name: String, url: String, tx_config: TransactionConfig, client: Client) -> Self {
+    pub fn new(name: String, rpc_config: RpcConfig, tx_config: TransactionConfig, client: Client) -> Self {
         Self {
-            url,
+            url: rpc_config.url_with_auth(),
+            auth_header: rpc_config.auth_header(),
             name,
             tx_config,
             client,
+            mode: rpc_config.jito_mode,
+            bundle: rpc_config.bundle,
         }
     }
 
@@ -46,6 +57,71 @@ impl JitoTxSender {
             associated_bonding_curve,
         )
     }
+
+    pub fn build_bundle(
+        &self,
+        index: u32,
+        recent_blockhash: Hash,
+        token_address: Pubkey,
+        bonding_curve: Pubkey,
+        associated_bonding_curve: Pubkey,
+    ) -> anyhow::Result<Vec<VersionedTransaction>> {
+        if self.bundle.is_empty() || self.bundle.len() > MAX_BUNDLE_SIZE {
+            return Err(anyhow::anyhow!(
+                "bundle for {} must have between 1 and {} transactions",
+                self.name,
+                MAX_BUNDLE_SIZE
+            ));
+        }
+        let separate_tip = self.bundle.contains(&BundleTx::Tip);
+
+        let bundle = self
+            .bundle
+            .iter()
+            .map(|bundle_tx| match bundle_tx {
+                // with a dedicated tip transaction the buy itself must not tip again
+                BundleTx::Buy if separate_tip => build_transaction_with_config(
+                    &self.tx_config,
+                    &RpcType::SolanaRpc,
+                    recent_blockhash,
+                    token_address,
+                    bonding_curve,
+                    associated_bonding_curve,
+                ),
+                BundleTx::Buy => self.build_transaction_with_config(
+                    index,
+                    recent_blockhash,
+                    token_address,
+                    bonding_curve,
+                    associated_bonding_curve,
+                ),
+                BundleTx::Tip => build_tip_transaction(&self.tx_config, recent_blockhash),
+            })
+            .collect();
+        Ok(bundle)
+    }
+
+    fn encode_transaction(tx: &VersionedTransaction) -> anyhow::Result<String> {
+        let tx_bytes = bincode::serialize(tx).context("cannot serialize tx to bincode")?;
+        Ok(bs58::encode(tx_bytes).into_string())
+    }
+
+    async fn post(&self, url: &str, body: Value) -> anyhow::Result<(Option<String>, JitoResponse)> {
+        debug!("sending tx: {}", body.to_string());
+        let mut request = self.client.post(url).json(&body);
+        if let Some((auth_name, auth)) = &self.auth_header {
+            request = request.header(auth_name, auth);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let bundle_id = response.headers().get("x-bundle-id").and_then(|value| value.to_str().ok()).map(String::from);
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("failed to send tx: {}", body));
+        }
+        let parsed_resp = serde_json::from_str::<JitoResponse>(&body).context("cannot deserialize signature")?;
+        Ok((bundle_id, parsed_resp))
+    }
 }
 
 #[derive(Deserialize)]
@@ -65,19 +141,56 @@ pub struct JitoBundleStatusResponseInnerValue {
 #[derive(Deserialize)]
 pub struct JitoBundleStatusResponseInner {
     pub context: JitoBundleStatusResponseInnerContext,
-    pub value: Vec<JitoBundleStatusResponseInnerValue>,
+    // unknown bundles come back as null
+    pub value: Vec<Option<JitoBundleStatusResponseInnerValue>>,
 }
 #[derive(Deserialize)]
 pub struct JitoBundleStatusResponse {
     pub result: JitoBundleStatusResponseInner,
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JitoInflightBundleStatus {
+    Invalid,
+    Pending,
+    Failed,
+    Landed,
+}
+
+#[derive(Deserialize)]
+pub struct JitoInflightBundleStatusResponseInnerValue {
+    pub bundle_id: String,
+    pub status: JitoInflightBundleStatus,
+    pub landed_slot: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct JitoInflightBundleStatusResponseInner {
+    pub context: JitoBundleStatusResponseInnerContext,
+    pub value: Vec<JitoInflightBundleStatusResponseInnerValue>,
+}
+
+#[derive(Deserialize)]
+pub struct JitoInflightBundleStatusResponse {
+    pub result: JitoInflightBundleStatusResponseInner,
+}
+
 #[derive(Deserialize)]
 pub struct JitoResponse {
-    //bundle id is response
+    // signature for sendTransaction, bundle id for sendBundle
     pub result: String,
 }
 
+/// Block engine bundle endpoint that sits next to the configured transactions endpoint.
+pub fn bundles_url(url: &str) -> String {
+    let (path, query) = url.split_once('?').map_or((url, None), |(path, query)| (path, Some(query)));
+    let path = path.strip_suffix("/transactions").map_or(path.to_string(), |base| format!("{}/bundles", base));
+    match query {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    }
+}
+
 #[async_trait]
 impl TxSender for JitoTxSender {
     fn name(&self) -> String {
@@ -92,29 +205,49 @@ impl TxSender for JitoTxSender {
         bonding_curve: Pubkey,
         associated_bonding_curve: Pubkey,
     ) -> anyhow::Result<TxResult> {
-        let tx = self.build_transaction_with_config(
-            index,
-            recent_blockhash,
-            token_address,
-            bonding_curve,
-            associated_bonding_curve,
-        );
-        let tx_bytes = bincode::serialize(&tx).context("cannot serialize tx to bincode")?;
-        let encoded_transaction = bs58::encode(tx_bytes).into_string();
-        let body = json!({
-            "jsonrpc": "2.0",
-            "id": 1,
-            "method": "sendTransaction",
-            "params": [encoded_transaction]
-        });
-        debug!("sending tx: {}", body.to_string());
-        let response = self.client.post(&self.url).json(&body).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
-        if !status.is_success() {
-            return Err(anyhow::anyhow!("failed to send tx: {}", body));
+        match self.mode {
+            JitoMode::Transaction => {
+                let tx = self.build_transaction_with_config(
+                    index,
+                    recent_blockhash,
+                    token_address,
+                    bonding_curve,
+                    associated_bonding_curve,
+                );
+                let body = json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "method": "sendTransaction",
+                    "params": [Self::encode_transaction(&tx)?]
+                });
+                // sendTransaction answers with the signature, the bundle it was wrapped in comes back as a header
+                let (bundle_id, parsed_resp) = self.post(&self.url, body).await?;
+                match bundle_id {
+                    Some(bundle_id) => Ok(TxResult::BundleID(bundle_id)),
+                    None => Ok(TxResult::Signature(
+                        Signature::from_str(&parsed_resp.result).context("cannot parse signature")?,
+                    )),
+                }
+            }
+            JitoMode::Bundle => {
+                let bundle = self.build_bundle(
+                    index,
+                    recent_blockhash,
+                    token_address,
+                    bonding_curve,
+                    associated_bonding_curve,
+                )?;
+                let encoded_transactions =
+                    bundle.iter().map(Self::encode_transaction).collect::<anyhow::Result<Vec<String>>>()?;
+                let body = json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "method": "sendBundle",
+                    "params": [encoded_transactions]
+                });
+                let (_, parsed_resp) = self.post(&bundles_url(&self.url), body).await?;
+                Ok(TxResult::BundleID(parsed_resp.result))
+            }
         }
-        let parsed_resp = serde_json::from_str::<JitoResponse>(&body).context("cannot deserialize signature")?;
-        Ok(TxResult::BundleID(parsed_resp.result))
     }
 }
@@ -10,9 +10,12 @@ use solana_sdk::signature::Signature;
 use std::sync::Arc;
 use tracing::info;
 
+pub mod compute_units;
 pub mod constants;
+pub mod fees;
 pub mod jito;
 pub mod solana_rpc;
+pub mod tip;
 pub mod transaction;
 
 #[derive(Debug, Clone)]
@@ -21,9 +24,9 @@ pub enum TxResult {
     BundleID(String),
 }
 
-impl Into<String> for TxResult {
-    fn into(self) -> String {
-        match self {
+impl From<TxResult> for String {
+    fn from(tx_result: TxResult) -> Self {
+        match tx_result {
             TxResult::Signature(sig) => sig.to_string(),
             TxResult::BundleID(bundle_id) => bundle_id,
         }
@@ -48,16 +51,16 @@ pub fn create_tx_sender(
     rpc_config: RpcConfig,
     tx_config: TransactionConfig,
     client: Client,
-) -> Arc<dyn TxSender> {
+) -> anyhow::Result<Arc<dyn TxSender>> {
     info!("create_tx_sender {:?}", rpc_config.rpc_type);
     match rpc_config.rpc_type {
         RpcType::SolanaRpc => {
-            let tx_sender = GenericRpc::new(name, rpc_config.url, tx_config, RpcType::SolanaRpc);
-            Arc::new(tx_sender)
+            let tx_sender = GenericRpc::new(name, rpc_config, tx_config, RpcType::SolanaRpc)?;
+            Ok(Arc::new(tx_sender))
         }
         RpcType::Jito => {
-            let tx_sender = JitoTxSender::new(name, rpc_config.url, tx_config, client);
-            Arc::new(tx_sender)
+            let tx_sender = JitoTxSender::new(name, rpc_config, tx_config, client);
+            Ok(Arc::new(tx_sender))
         }
     }
 }
@@ -1,15 +1,20 @@
-use crate::config::RpcType;
+use crate::config::{RpcConfig, RpcType};
 use crate::tx_senders::transaction::{TransactionConfig, build_transaction_with_config};
 use crate::tx_senders::{TxResult, TxSender};
 use anyhow::Context;
 use async_trait::async_trait;
+use reqwest011::header::{HeaderName, HeaderValue};
 use serde::Serialize;
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::RpcClientConfig;
 use solana_client::rpc_config::RpcSendTransactionConfig;
+use solana_rpc_client::http_sender::HttpSender;
 use solana_sdk::hash::Hash;
 use solana_sdk::pubkey::Pubkey;
 use solana_transaction_status::UiTransactionEncoding;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct GenericRpc {
@@ -19,7 +24,7 @@ pub struct GenericRpc {
     rpc_type: RpcType,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub struct TxMetrics {
     pub rpc_name: String,
     pub signature: String,
@@ -29,17 +34,39 @@ pub struct TxMetrics {
     pub slot_landed: Option<u64>,
     pub slot_latency: Option<u64>,
     pub elapsed: Option<u64>, // in milliseconds
+    pub confirmation_status: Option<String>,
+    pub err: Option<String>,
 }
 
 impl GenericRpc {
-    pub fn new(name: String, url: String, config: TransactionConfig, rpc_type: RpcType) -> Self {
-        let http_rpc = Arc::new(RpcClient::new(url));
-        GenericRpc {
+    pub fn new(
+        name: String,
+        rpc_config: RpcConfig,
+        config: TransactionConfig,
+        rpc_type: RpcType,
+    ) -> anyhow::Result<Self> {
+        let mut headers = HttpSender::default_headers();
+        if let Some((auth_name, auth)) = rpc_config.auth_header() {
+            headers.insert(
+                HeaderName::from_str(&auth_name).context("invalid auth_name")?,
+                HeaderValue::from_str(&auth).context("invalid auth")?,
+            );
+        }
+        let timeout = Duration::from_secs(30);
+        let client = reqwest011::Client::builder()
+            .default_headers(headers)
+            .timeout(timeout)
+            .pool_idle_timeout(timeout)
+            .build()
+            .context("cannot build rpc client")?;
+        let sender = HttpSender::new_with_client(rpc_config.url_with_auth(), client);
+        let http_rpc = Arc::new(RpcClient::new_sender(sender, RpcClientConfig::default()));
+        Ok(GenericRpc {
             name,
             http_rpc,
             tx_config: config,
             rpc_type,
-        }
+        })
     }
 }
 
@@ -0,0 +1,184 @@
+use crate::config::{TipAccountSelection, TipMode, TipStrategyConfig};
+use crate::tx_senders::constants::JITO_TIP_ACCOUNTS;
+use anyhow::Context;
+use rand::Rng;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{debug, error};
+
+#[derive(Deserialize)]
+pub struct JitoTipAccountsResponse {
+    pub result: Vec<String>,
+}
+
+// tip amounts are in sol
+#[derive(Deserialize, Debug)]
+pub struct JitoTipFloor {
+    pub landed_tips_25th_percentile: f64,
+    pub landed_tips_50th_percentile: f64,
+    pub landed_tips_75th_percentile: f64,
+    pub landed_tips_95th_percentile: f64,
+    pub landed_tips_99th_percentile: f64,
+}
+
+impl JitoTipFloor {
+    pub fn percentile(&self, percentile: u8) -> anyhow::Result<f64> {
+        match percentile {
+            25 => Ok(self.landed_tips_25th_percentile),
+            50 => Ok(self.landed_tips_50th_percentile),
+            75 => Ok(self.landed_tips_75th_percentile),
+            95 => Ok(self.landed_tips_95th_percentile),
+            99 => Ok(self.landed_tips_99th_percentile),
+            _ => Err(anyhow::anyhow!("unsupported tip percentile {}", percentile)),
+        }
+    }
+}
+
+/// Tip amount used by `build_transaction_with_config`, either fixed or tracking the tip floor through a background
+/// refresh.
+pub struct TipStrategy {
+    config: TipStrategyConfig,
+    // in lamports
+    current: AtomicU64,
+}
+
+impl TipStrategy {
+    pub fn new(tip: u64, config: TipStrategyConfig) -> Self {
+        let strategy = TipStrategy {
+            config,
+            current: AtomicU64::new(0),
+        };
+        strategy.set(tip);
+        strategy
+    }
+
+    pub fn current(&self) -> u64 {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, tip: u64) {
+        let min = self.config.min.map(sol_to_lamports).unwrap_or(0);
+        let max = self.config.max.map(sol_to_lamports).unwrap_or(u64::MAX);
+        self.current.store(tip.clamp(min, max.max(min)), Ordering::Relaxed);
+    }
+
+    pub fn spawn_refresh(self: Arc<Self>, client: Client) {
+        if !matches!(self.config.mode, TipMode::Percentile) {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(self.config.refresh_ms));
+            loop {
+                interval.tick().await;
+                match self.fetch_tip(&client).await {
+                    Ok(tip) => {
+                        self.set(tip);
+                        debug!(
+                            "tip p{} is {} lamports, using {}",
+                            self.config.percentile,
+                            tip,
+                            self.current()
+                        );
+                    }
+                    Err(e) => error!("cannot refresh tip, keeping {} lamports: {:?}", self.current(), e),
+                }
+            }
+        });
+    }
+
+    async fn fetch_tip(&self, client: &Client) -> anyhow::Result<u64> {
+        let response = client.get(&self.config.tip_floor_url).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("failed to fetch tip floor: {}", body));
+        }
+        let tip_floors = serde_json::from_str::<Vec<JitoTipFloor>>(&body).context("cannot deserialize tip floor")?;
+        let tip_floor = tip_floors.first().context("empty tip floor")?;
+        Ok(sol_to_lamports(tip_floor.percentile(self.config.percentile)?))
+    }
+}
+
+fn sol_to_lamports(sol: f64) -> u64 {
+    (sol * LAMPORTS_PER_SOL as f64) as u64
+}
+
+/// Set of Jito tip accounts, one of which is picked per transaction so our buys don't all write-lock the same
+/// account.
+pub struct TipAccounts {
+    accounts: RwLock<Vec<Pubkey>>,
+    selection: TipAccountSelection,
+    next: AtomicUsize,
+}
+
+impl TipAccounts {
+    pub fn new(accounts: Vec<Pubkey>, selection: TipAccountSelection) -> Self {
+        TipAccounts {
+            accounts: RwLock::new(accounts),
+            selection,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn from_config(accounts: Option<&Vec<String>>, selection: TipAccountSelection) -> anyhow::Result<Self> {
+        let accounts = match accounts {
+            Some(accounts) => accounts.iter().map(|account| Pubkey::from_str(account)).collect::<Result<Vec<_>, _>>(),
+            None => JITO_TIP_ACCOUNTS.iter().map(|account| Pubkey::from_str(account)).collect(),
+        }
+        .context("invalid tip account")?;
+        if accounts.is_empty() {
+            return Err(anyhow::anyhow!("tip_accounts cannot be empty"));
+        }
+        Ok(Self::new(accounts, selection))
+    }
+
+    pub fn pick(&self) -> Pubkey {
+        let accounts = self.accounts.read().unwrap();
+        let index = match self.selection {
+            TipAccountSelection::Random => rand::thread_rng().gen_range(0..accounts.len()),
+            TipAccountSelection::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % accounts.len(),
+        };
+        accounts[index]
+    }
+
+    pub fn replace(&self, accounts: Vec<Pubkey>) {
+        if !accounts.is_empty() {
+            *self.accounts.write().unwrap() = accounts;
+        }
+    }
+
+    /// Fetches the current tip accounts from the block engine bundles endpoint.
+    pub async fn fetch(
+        client: &Client,
+        url: &str,
+        auth_header: Option<(String, String)>,
+    ) -> anyhow::Result<Vec<Pubkey>> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "getTipAccounts",
+            "params": []
+        });
+        let mut request = client.post(url).json(&body);
+        if let Some((auth_name, auth)) = auth_header {
+            request = request.header(auth_name, auth);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("failed to fetch tip accounts: {}", body));
+        }
+        let parsed_resp =
+            serde_json::from_str::<JitoTipAccountsResponse>(&body).context("cannot deserialize tip accounts")?;
+        parsed_resp.result.iter().map(|account| Pubkey::from_str(account).context("invalid tip account")).collect()
+    }
+}
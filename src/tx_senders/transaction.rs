@@ -1,8 +1,13 @@
 use crate::config::{PingThingsArgs, RpcType};
+use crate::pumpfun::bonding_curve::BondingCurve;
+use crate::tx_senders::compute_units::ComputeUnitSizer;
 use crate::tx_senders::constants::{
-    JITO_TIP_ADDR, PUMP_FUN_ACCOUNT_ADDR, PUMP_FUN_PROGRAM_ADDR, PUMP_FUN_TX_ADDR, RENT_ADDR, SYSTEM_PROGRAM_ADDR,
-    TOKEN_PROGRAM_ADDR,
+    PUMP_FUN_ACCOUNT_ADDR, PUMP_FUN_FEE_RECIPIENT_ADDR, PUMP_FUN_PROGRAM_ADDR, PUMP_FUN_TX_ADDR, RENT_ADDR,
+    SYSTEM_PROGRAM_ADDR, TOKEN_PROGRAM_ADDR,
 };
+use crate::tx_senders::fees::PriorityFeeEstimator;
+use crate::tx_senders::tip::{TipAccounts, TipStrategy};
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::compute_budget::ComputeBudgetInstruction;
 use solana_sdk::hash::Hash;
 use solana_sdk::instruction::{AccountMeta, Instruction};
@@ -21,31 +26,56 @@ use std::sync::Arc;
 #[derive(Clone)]
 pub struct TransactionConfig {
     pub keypair: Arc<Keypair>,
-    pub compute_unit_limit: u32,
-    pub compute_unit_price: u64,
-    pub tip: u64,
+    pub compute_unit_limit: Arc<ComputeUnitSizer>,
+    pub compute_unit_price: Arc<PriorityFeeEstimator>,
+    pub tip: Arc<TipStrategy>,
+    pub tip_accounts: Arc<TipAccounts>,
     pub buy_amount: u64,
     pub min_amount_out: u64,
 }
 
-impl From<PingThingsArgs> for TransactionConfig {
-    fn from(args: PingThingsArgs) -> Self {
+impl TryFrom<PingThingsArgs> for TransactionConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(args: PingThingsArgs) -> anyhow::Result<Self> {
         let keypair = Keypair::from_base58_string(args.private_key.as_str());
 
         let tip: u64 = (args.tip * LAMPORTS_PER_SOL as f64) as u64;
         let buy_amount: u64 = (args.buy_amount * LAMPORTS_PER_SOL as f64) as u64;
-        let min_amount_out: u64 = (args.min_amount_out * 1_000_000 as f64) as u64;
+        let tip_accounts = TipAccounts::from_config(args.tip_accounts.as_ref(), args.tip_account_selection)?;
+        let min_amount_out: u64 = (args.min_amount_out * 1_000_000f64) as u64;
 
-        TransactionConfig {
+        Ok(TransactionConfig {
             keypair: Arc::new(keypair),
-            compute_unit_limit: args.compute_unit_limit,
-            compute_unit_price: args.compute_unit_price,
-            tip,
+            compute_unit_limit: Arc::new(ComputeUnitSizer::new(
+                args.compute_unit_limit,
+                args.compute_units,
+                Arc::new(RpcClient::new(args.http_rpc)),
+            )),
+            compute_unit_price: Arc::new(PriorityFeeEstimator::new(args.compute_unit_price, args.priority_fee)),
+            tip: Arc::new(TipStrategy::new(tip, args.tip_strategy)),
+            tip_accounts: Arc::new(tip_accounts),
             buy_amount,
             min_amount_out,
-        }
+        })
     }
 }
+fn build_tip_instruction(tx_config: &TransactionConfig, tip: u64) -> Instruction {
+    system_instruction::transfer(&tx_config.keypair.pubkey(), &tx_config.tip_accounts.pick(), tip)
+}
+
+/// Standalone tip transfer, used as its own transaction inside a Jito bundle.
+pub fn build_tip_transaction(tx_config: &TransactionConfig, recent_blockhash: Hash) -> VersionedTransaction {
+    let owner = tx_config.keypair.pubkey();
+    let instructions = vec![build_tip_instruction(tx_config, tx_config.tip.current())];
+
+    let message_v0 = Message::try_compile(&owner, instructions.as_slice(), &[], recent_blockhash).unwrap();
+
+    let versioned_message = VersionedMessage::V0(message_v0);
+
+    VersionedTransaction::try_new(versioned_message, &[&tx_config.keypair]).unwrap()
+}
+
 pub fn build_transaction_with_config(
     tx_config: &TransactionConfig,
     rpc_type: &RpcType,
@@ -56,28 +86,15 @@ pub fn build_transaction_with_config(
 ) -> VersionedTransaction {
     let mut instructions = Vec::new();
 
-    if tx_config.compute_unit_limit > 0 {
-        let compute_unit_limit = ComputeBudgetInstruction::set_compute_unit_limit(tx_config.compute_unit_limit);
-        instructions.push(compute_unit_limit);
-    }
-
-    if tx_config.compute_unit_price > 0 {
-        let compute_unit_price = ComputeBudgetInstruction::set_compute_unit_price(tx_config.compute_unit_price);
-        instructions.push(compute_unit_price);
-    }
-
-    if tx_config.tip > 0 {
+    let tip = tx_config.tip.current();
+    if tip > 0 {
         let tip_instruction: Option<Instruction> = match rpc_type {
-            RpcType::Jito => Some(system_instruction::transfer(
-                &tx_config.keypair.pubkey(),
-                &Pubkey::from_str(JITO_TIP_ADDR).unwrap(),
-                tx_config.tip,
-            )),
+            RpcType::Jito => Some(build_tip_instruction(tx_config, tip)),
             _ => None,
         };
 
-        if tip_instruction.is_some() {
-            instructions.push(tip_instruction.unwrap());
+        if let Some(tip_instruction) = tip_instruction {
+            instructions.push(tip_instruction);
         }
     }
 
@@ -106,10 +123,7 @@ pub fn build_transaction_with_config(
 
     let accounts = vec![
         AccountMeta::new_readonly(pump_fun_account_pubkey, false),
-        AccountMeta::new(
-            Pubkey::from_str("CebN5WGQ4jvEPvsVU4EoHEpgzq1VV7AbicfhtW4xC9iM").unwrap(),
-            false,
-        ),
+        AccountMeta::new(Pubkey::from_str(PUMP_FUN_FEE_RECIPIENT_ADDR).unwrap(), false),
         AccountMeta::new_readonly(token_address, false),
         AccountMeta::new(bonding_curve, false),
         AccountMeta::new(associated_bonding_curve, false),
@@ -130,9 +144,45 @@ pub fn build_transaction_with_config(
 
     instructions.push(swap_instruction);
 
+    // compute budget goes first but its limit depends on the rest of the transaction
+    let mut compute_budget_instructions = Vec::new();
+
+    let compute_unit_limit = tx_config.compute_unit_limit.limit_for(&instructions);
+    if compute_unit_limit > 0 {
+        let compute_unit_limit = ComputeBudgetInstruction::set_compute_unit_limit(compute_unit_limit);
+        compute_budget_instructions.push(compute_unit_limit);
+    }
+
+    let compute_unit_price = tx_config.compute_unit_price.current();
+    if compute_unit_price > 0 {
+        let compute_unit_price = ComputeBudgetInstruction::set_compute_unit_price(compute_unit_price);
+        compute_budget_instructions.push(compute_unit_price);
+    }
+
+    tx_config.compute_unit_limit.size_in_background(&owner, &instructions, compute_unit_price, recent_blockhash);
+    instructions.splice(0..0, compute_budget_instructions);
+
     let message_v0 = Message::try_compile(&owner, instructions.as_slice(), &[], recent_blockhash).unwrap();
 
     let versioned_message = VersionedMessage::V0(message_v0);
 
     VersionedTransaction::try_new(versioned_message, &[&tx_config.keypair]).unwrap()
 }
+
+/// Builds and drops a buy of `mint` for every sender type so `ComputeUnitSizer` simulates their layouts before the
+/// first real buy. `mint` has to be a token we do not hold, on a bonding curve that still trades.
+pub fn warm_up_compute_units(tx_config: &TransactionConfig, mint: Pubkey) {
+    let bonding_curve = BondingCurve::find_address(&mint);
+    let associated_bonding_curve = get_associated_token_address(&bonding_curve, &mint);
+    for rpc_type in [RpcType::SolanaRpc, RpcType::Jito] {
+        // the blockhash is replaced by the simulation
+        build_transaction_with_config(
+            tx_config,
+            &rpc_type,
+            Hash::default(),
+            mint,
+            bonding_curve,
+            associated_bonding_curve,
+        );
+    }
+}